@@ -0,0 +1,75 @@
+// Generates the header `lua_function_at_line.h` in `OUT_DIR` from `src/lib.rs` with cbindgen.
+// The test `header_is_up_to_date` checks that `include/lua_function_at_line.h` is the same,
+// so the generated header has to be copied there after changing the C API.
+//
+// Also generates the pkg-config file `lua_function_at_line_c.pc` in `OUT_DIR`, from where
+// `examples/pkg_config.rs` prints it. By default it points at the build directory and at
+// `include` in this crate, so that C programs can be built against an uninstalled library.
+// If `PREFIX` is set, it points at `$PREFIX/lib` and `$PREFIX/include` instead,
+// for installing the library.
+
+use std::{env, fs, path::PathBuf};
+
+// The system libraries that the standard library needs when it is linked statically,
+// as `rustc --print native-static-libs` reports them for a static library built for the target.
+// For other targets `Libs.private` is left empty, and that command lists the libraries to add.
+fn native_static_libs() -> &'static str {
+    let os = env::var("CARGO_CFG_TARGET_OS").unwrap();
+    let target_env = env::var("CARGO_CFG_TARGET_ENV").unwrap();
+    match (os.as_str(), target_env.as_str()) {
+        ("linux", "gnu") => "-lgcc_s -lutil -lrt -lpthread -lm -ldl -lc",
+        ("macos", _) => "-liconv -lSystem -lc -lm",
+        _ => {
+            println!("cargo:warning=`Libs.private` in the pkg-config file is empty for this target");
+            ""
+        }
+    }
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-env-changed=PREFIX");
+
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    // `OUT_DIR` is `target/<profile>/build/<package>-<hash>/out`.
+    let profile_dir = out_dir.ancestors().nth(3).unwrap().to_owned();
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+
+    let config = cbindgen::Config::from_file(manifest_dir.join("cbindgen.toml")).unwrap();
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(manifest_dir.join("src").join("lib.rs"))
+        .generate()
+        .expect("could not generate the header")
+        .write_to_file(out_dir.join("lua_function_at_line.h"));
+
+    let (prefix, libdir, includedir) = match env::var("PREFIX") {
+        Ok(prefix) => (prefix, "${prefix}/lib".to_string(), "${prefix}/include".to_string()),
+        Err(_) => (
+            profile_dir.display().to_string(),
+            "${prefix}".to_string(),
+            manifest_dir.join("include").display().to_string(),
+        ),
+    };
+    let pc = format!(
+        "prefix={prefix}
+libdir={libdir}
+includedir={includedir}
+
+Name: lua_function_at_line_c
+Description: Find the function containing a line of Lua code
+Version: {version}
+Libs: -L${{libdir}} -llua_function_at_line_c
+Libs.private: {libs_private}
+Cflags: -I${{includedir}}
+",
+        prefix = prefix,
+        libdir = libdir,
+        includedir = includedir,
+        version = env::var("CARGO_PKG_VERSION").unwrap(),
+        libs_private = native_static_libs(),
+    );
+    fs::write(out_dir.join("lua_function_at_line_c.pc"), &pc).unwrap();
+}
@@ -0,0 +1,9 @@
+// Prints the pkg-config file that the build script generates for the libraries.
+// For the libraries in the build directory:
+// $ cargo run --release --example pkg_config > target/release/lua_function_at_line_c.pc
+// For installing them under a prefix:
+// $ PREFIX=/usr/local cargo run --release --example pkg_config > /usr/local/lib/pkgconfig/lua_function_at_line_c.pc
+
+fn main() {
+    print!("{}", include_str!(concat!(env!("OUT_DIR"), "/lua_function_at_line_c.pc")));
+}
@@ -1,30 +1,155 @@
+// All functions accept `NULL` for any pointer argument: functions that return a value return
+// `NULL`, zero, `false` or -1 (as documented for each function), and freeing `NULL` does nothing.
+// Out-parameters that are `NULL` are not written to. Other pointers must be valid.
+// No function unwinds into the caller: a panic in the analyzer is caught and reported as
+// `LUA_FUNCTION_LINES_ERROR_INTERNAL`, or as the "not found" result of the function.
+
 #ifndef LUA_FUNCTION_AT_LINE_H
 #define LUA_FUNCTION_AT_LINE_H
 
+// Generated from `src/lib.rs` by `build.rs`. Do not edit.
+
+#include <stdbool.h>
 #include <stdlib.h>
 
-#ifdef __cplusplus
-extern "C" {
-#endif
+// Version of the ABI described by this header. Compare it with `lua_function_at_line_version()`
+// to check that the library that is loaded at runtime matches.
+#define LUA_FUNCTION_AT_LINE_ABI_VERSION 1
+
+// `local function f() end` or `local f = function() end`
+#define LUA_FUNCTION_KIND_LOCAL 0
+
+// `function f() end` or `f = function() end`
+#define LUA_FUNCTION_KIND_GLOBAL 1
+
+// `function t.f() end`, `t.f = function() end` or `{ f = function() end }`
+#define LUA_FUNCTION_KIND_FIELD 2
+
+// `function t:f() end`
+#define LUA_FUNCTION_KIND_METHOD 3
+
+// a function that is not assigned to anything
+#define LUA_FUNCTION_KIND_ANONYMOUS 4
+
+// The code contains an invalid token, such as an unclosed string.
+#define LUA_FUNCTION_LINES_ERROR_TOKENIZE 0
+
+// The code contains a syntax error.
+#define LUA_FUNCTION_LINES_ERROR_PARSE 1
+
+// The code contains syntax that the function finder does not understand.
+#define LUA_FUNCTION_LINES_ERROR_ANALYSIS 2
+
+// `code` is `NULL` while `code_len` is not 0.
+#define LUA_FUNCTION_LINES_ERROR_INVALID_ARGUMENT 3
+
+// The analyzer panicked. This is a bug.
+#define LUA_FUNCTION_LINES_ERROR_INTERNAL 4
+
+// The code is nested too deeply, for instance in hundreds of nested tables.
+#define LUA_FUNCTION_LINES_ERROR_NESTING 5
+
+// Describes why Lua code could not be analyzed. Must be allocated and freed by Rust.
+typedef struct lua_function_lines_error lua_function_lines_error;
 
 // Object containing information on which functions are at given lines in a Lua module.
 // Must be allocated and freed by Rust.
+// Lines are zero-indexed throughout this API: line 0 is the line that Lua reports as line 1.
 typedef struct lua_module_function_lines lua_module_function_lines;
 
+// Information on one function in a Lua module.
+typedef struct lua_function_info {
+  // zero-indexed line of the start of the function
+  size_t start_line;
+  // zero-indexed line of the `end` of the function
+  size_t end_line;
+  // Name of the function, not zero-terminated. Valid until the module is freed.
+  const char *name;
+  size_t name_len;
+  // a `LUA_FUNCTION_KIND_*` value
+  int kind;
+} lua_function_info;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Gets the ABI version of the library.
+unsigned int lua_function_at_line_version(void);
+
 // Generate new object from Lua code. Provide length of memory area pointed to by `code` in `code_len`.
+// The code does not have to be valid UTF-8: bytes that are not part of valid UTF-8, for instance in Latin-1 strings
+// and comments, are each treated as one character, so lines and columns match the original code.
+// `code` may be `NULL` if `code_len` is 0. Returns `NULL` if the code cannot be analyzed.
 // Call `lua_module_function_lines_free` to free it.
-lua_module_function_lines * lua_module_function_lines_new(const char * code, size_t code_len);
+struct lua_module_function_lines *lua_module_function_lines_new(const char *code,
+                                                                size_t code_len);
+
+// Like `lua_module_function_lines_new`, but if the code cannot be analyzed and `error` is not `NULL`,
+// sets `*error` to an object describing the failure, which must be freed with `lua_function_lines_error_free`.
+// Sets `*error` to `NULL` on success.
+struct lua_module_function_lines *lua_module_function_lines_new_with_error(const char *code,
+                                                                           size_t code_len,
+                                                                           struct lua_function_lines_error **error);
+
+// Gets the `LUA_FUNCTION_LINES_ERROR_*` value of the error, or -1 if `error` is `NULL`.
+int lua_function_lines_error_get_kind(const struct lua_function_lines_error *error);
+
+// Gets the zero-terminated error message, for instance from full_moon. Provides its length in `message_len` if it is not `NULL`.
+// The message is valid until the error is freed. Returns `NULL` if `error` is `NULL`.
+const char *lua_function_lines_error_get_message(const struct lua_function_lines_error *error,
+                                                 size_t *message_len);
+
+// Gets the zero-indexed line and column (in characters) where the error was found.
+// Returns `false` and leaves `line` and `column` unchanged if the position is not known or `error` is `NULL`.
+bool lua_function_lines_error_get_position(const struct lua_function_lines_error *error,
+                                           size_t *line,
+                                           size_t *column);
+
+// Send the error to this function to be deallocated. Does nothing if `error` is `NULL`.
+void lua_function_lines_error_free(struct lua_function_lines_error *error);
+
+// Gets name of the innermost function with some part on `line` (zero-indexed), including the lines
+// of its `function` keyword and its `end`. Provides length of function name in `name_len`.
+// Return value is not guaranteed to be zero-terminated. If the line does not correspond to a function
+// or `module` is `NULL`, returns `NULL` and sets `name_len` to `(size_t) -1`.
+const char *lua_module_function_lines_get(const struct lua_module_function_lines *module,
+                                          size_t line,
+                                          size_t *name_len);
+
+// Gets the number of functions in the module, including nested and anonymous functions, or 0 if `module` is `NULL`.
+size_t lua_module_function_lines_count(const struct lua_module_function_lines *module);
+
+// Fills in `info` with the function at `index`, which is less than `lua_module_function_lines_count(module)`.
+// Functions are ordered by their start; nested functions come after the function containing them.
+// Returns `false` and leaves `info` unchanged if `index` is out of range or `module` or `info` is `NULL`.
+bool lua_module_function_lines_function(const struct lua_module_function_lines *module,
+                                        size_t index,
+                                        struct lua_function_info *info);
+
+// Gets the functions with some part on `line` (zero-indexed), from outermost to innermost, as indices
+// for `lua_module_function_lines_function`. Several functions on the same line that are not nested in each other
+// are all included. Writes at most `capacity` indices to `indices` and returns the total number of functions,
+// so that a caller can retry with a larger array if the return value is greater than `capacity`.
+// Returns 0 if `module` is `NULL`.
+size_t lua_module_function_lines_stack(const struct lua_module_function_lines *module,
+                                       size_t line,
+                                       size_t *indices,
+                                       size_t capacity);
 
-// Gets name of function at `line` (zero-indexed). Provides length of function name in `name_len`.
-// Return value is not guaranteed to be zero-terminated. If the line does not correspond to a function,
-// returns `NULL` and sets `name_len` to `(size_t) -1`.
-const char * lua_module_function_lines_get(const lua_module_function_lines * module, size_t line, size_t * name_len);
+// Gets the index of the innermost function containing the character at `line` and `column` (both zero-indexed,
+// the column counted in characters). Returns `false` and leaves `index` unchanged if there is no such function
+// or `module` is `NULL`.
+bool lua_module_function_lines_at(const struct lua_module_function_lines *module,
+                                  size_t line,
+                                  size_t column,
+                                  size_t *index);
 
-// Send the object to this function to be deallocated.
-void lua_module_function_lines_free(lua_module_function_lines * module);
+// Send the object to this function to be deallocated. Does nothing if `module` is `NULL`.
+void lua_module_function_lines_free(struct lua_module_function_lines *module);
 
 #ifdef __cplusplus
-}
-#endif
+}  // extern "C"
+#endif  // __cplusplus
 
-#endif
+#endif  /* LUA_FUNCTION_AT_LINE_H */
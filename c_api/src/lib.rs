@@ -1,32 +1,236 @@
-use std::os::raw::c_char;
+// `include/lua_function_at_line.h` is generated from the items in this file with cbindgen
+// (see `build.rs`), so the comments starting with `///` are the documentation of the C API.
+// Every function accepts NULL pointers and catches panics, so that they do not unwind into C.
+#![allow(clippy::missing_safety_doc)]
 
-use lua_function_at_line::Module;
+use std::{any::Any, ffi::CString, os::raw::{c_char, c_int, c_uint}, panic::{catch_unwind, AssertUnwindSafe}};
 
+use lua_function_at_line::{decode_source, ErrorKind, FunctionKind, LineBase, Module, Options};
+
+/// Version of the ABI described by this header. Compare it with `lua_function_at_line_version()`
+/// to check that the library that is loaded at runtime matches.
+// Incremented whenever a function or type in the header changes incompatibly.
+pub const LUA_FUNCTION_AT_LINE_ABI_VERSION: c_uint = 1;
+
+// Lines are zero-indexed everywhere in the C API.
+const LINE_BASE: LineBase = LineBase::Zero;
+
+// Values of the `kind` of a `lua_function_info`: what a function is assigned to, if anything.
+/// `local function f() end` or `local f = function() end`
+pub const LUA_FUNCTION_KIND_LOCAL: c_int = 0;
+/// `function f() end` or `f = function() end`
+pub const LUA_FUNCTION_KIND_GLOBAL: c_int = 1;
+/// `function t.f() end`, `t.f = function() end` or `{ f = function() end }`
+pub const LUA_FUNCTION_KIND_FIELD: c_int = 2;
+/// `function t:f() end`
+pub const LUA_FUNCTION_KIND_METHOD: c_int = 3;
+/// a function that is not assigned to anything
+pub const LUA_FUNCTION_KIND_ANONYMOUS: c_int = 4;
+
+/// Information on one function in a Lua module.
+#[repr(C)]
+pub struct LuaFunctionInfo {
+    /// zero-indexed line of the start of the function
+    start_line: usize,
+    /// zero-indexed line of the `end` of the function
+    end_line: usize,
+    /// Name of the function, not zero-terminated. Valid until the module is freed.
+    name: *const c_char,
+    name_len: usize,
+    /// a `LUA_FUNCTION_KIND_*` value
+    kind: c_int,
+}
+
+// Values of `lua_function_lines_error_get_kind`.
+/// The code contains an invalid token, such as an unclosed string.
+pub const LUA_FUNCTION_LINES_ERROR_TOKENIZE: c_int = 0;
+/// The code contains a syntax error.
+pub const LUA_FUNCTION_LINES_ERROR_PARSE: c_int = 1;
+/// The code contains syntax that the function finder does not understand.
+pub const LUA_FUNCTION_LINES_ERROR_ANALYSIS: c_int = 2;
+/// `code` is `NULL` while `code_len` is not 0.
+pub const LUA_FUNCTION_LINES_ERROR_INVALID_ARGUMENT: c_int = 3;
+/// The analyzer panicked. This is a bug.
+pub const LUA_FUNCTION_LINES_ERROR_INTERNAL: c_int = 4;
+/// The code is nested too deeply, for instance in hundreds of nested tables.
+pub const LUA_FUNCTION_LINES_ERROR_NESTING: c_int = 5;
+
+/// Object containing information on which functions are at given lines in a Lua module.
+/// Must be allocated and freed by Rust.
+/// Lines are zero-indexed throughout this API: line 0 is the line that Lua reports as line 1.
+pub struct LuaModuleFunctionLines(Module);
+
+/// Describes why Lua code could not be analyzed. Must be allocated and freed by Rust.
+pub struct LuaFunctionLinesError {
+    kind: c_int,
+    message: CString,
+    // zero-based line and column
+    position: Option<(usize, usize)>,
+}
+
+impl LuaFunctionLinesError {
+    fn new(kind: c_int, message: String, position: Option<(usize, usize)>) -> Self {
+        let message = CString::new(message.replace('\0', "\\0")).unwrap_or_default();
+        LuaFunctionLinesError { kind, message, position }
+    }
+}
+
+// Runs `f`, returning `default` if it panics.
+fn catch_panic<T>(default: T, f: impl FnOnce() -> T) -> T {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(default)
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
+
+fn parse(code: &[u8]) -> Result<LuaModuleFunctionLines, LuaFunctionLinesError> {
+    let options = Options {
+        line_base: LINE_BASE,
+        ..Options::default()
+    };
+    Module::parse_with(&decode_source(code), &options).map(LuaModuleFunctionLines).map_err(|e| {
+        let kind = match e.kind {
+            ErrorKind::Tokenize => LUA_FUNCTION_LINES_ERROR_TOKENIZE,
+            ErrorKind::Parse => LUA_FUNCTION_LINES_ERROR_PARSE,
+            ErrorKind::Analysis => LUA_FUNCTION_LINES_ERROR_ANALYSIS,
+            ErrorKind::Nesting => LUA_FUNCTION_LINES_ERROR_NESTING,
+            // `parser_stack_size` is not set, so no thread is started.
+            ErrorKind::Resource => LUA_FUNCTION_LINES_ERROR_INTERNAL,
+            // The C API only parses Lua 5.1.
+            ErrorKind::Unsupported => LUA_FUNCTION_LINES_ERROR_INVALID_ARGUMENT,
+        };
+        let position = e
+            .position
+            .map(|(line, column)| (LINE_BASE.from_one_based(line), column.saturating_sub(1)));
+        LuaFunctionLinesError::new(kind, e.message, position)
+    })
+}
+
+/// Gets the ABI version of the library.
+#[no_mangle]
+pub extern "C" fn lua_function_at_line_version() -> c_uint {
+    LUA_FUNCTION_AT_LINE_ABI_VERSION
+}
+
+/// Generate new object from Lua code. Provide length of memory area pointed to by `code` in `code_len`.
+/// The code does not have to be valid UTF-8: bytes that are not part of valid UTF-8, for instance in Latin-1 strings
+/// and comments, are each treated as one character, so lines and columns match the original code.
+/// `code` may be `NULL` if `code_len` is 0. Returns `NULL` if the code cannot be analyzed.
+/// Call `lua_module_function_lines_free` to free it.
 #[no_mangle]
-pub unsafe extern "C" fn lua_module_function_lines_new(code: *const c_char, code_len: usize) -> *mut Module {
-    unsafe fn inner(code: *const c_char, code_len: usize) -> Option<*mut Module> {
-        let code = &*std::ptr::slice_from_raw_parts(code as *const u8, code_len);
-        let code = std::str::from_utf8(code).ok()?;
-        Module::new(code).map(|m| Box::into_raw(Box::new(m)))
+pub unsafe extern "C" fn lua_module_function_lines_new(code: *const c_char, code_len: usize) -> *mut LuaModuleFunctionLines {
+    lua_module_function_lines_new_with_error(code, code_len, std::ptr::null_mut())
+}
+
+/// Like `lua_module_function_lines_new`, but if the code cannot be analyzed and `error` is not `NULL`,
+/// sets `*error` to an object describing the failure, which must be freed with `lua_function_lines_error_free`.
+/// Sets `*error` to `NULL` on success.
+#[no_mangle]
+pub unsafe extern "C" fn lua_module_function_lines_new_with_error(code: *const c_char, code_len: usize, error: *mut *mut LuaFunctionLinesError) -> *mut LuaModuleFunctionLines {
+    let result = if code.is_null() && code_len != 0 {
+        Err(LuaFunctionLinesError::new(LUA_FUNCTION_LINES_ERROR_INVALID_ARGUMENT, "code is NULL".to_string(), None))
+    } else {
+        let code = if code.is_null() {
+            &[]
+        } else {
+            &*std::ptr::slice_from_raw_parts(code as *const u8, code_len)
+        };
+        catch_unwind(|| parse(code)).unwrap_or_else(|payload| {
+            let message = format!("internal error: {}", panic_message(payload));
+            Err(LuaFunctionLinesError::new(LUA_FUNCTION_LINES_ERROR_INTERNAL, message, None))
+        })
+    };
+    match result {
+        Ok(module) => {
+            if !error.is_null() {
+                *error = std::ptr::null_mut();
+            }
+            Box::into_raw(Box::new(module))
+        }
+        Err(e) => {
+            if !error.is_null() {
+                *error = Box::into_raw(Box::new(e));
+            }
+            std::ptr::null_mut()
+        }
     }
-    match inner(code, code_len) {
-        Some(ptr) => ptr,
-        None => std::ptr::null::<Module>() as _,
+}
+
+/// Gets the `LUA_FUNCTION_LINES_ERROR_*` value of the error, or -1 if `error` is `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn lua_function_lines_error_get_kind(error: *const LuaFunctionLinesError) -> c_int {
+    match error.as_ref() {
+        Some(error) => error.kind,
+        None => -1,
     }
 }
 
+/// Gets the zero-terminated error message, for instance from full_moon. Provides its length in `message_len` if it is not `NULL`.
+/// The message is valid until the error is freed. Returns `NULL` if `error` is `NULL`.
 #[no_mangle]
-pub unsafe extern "C" fn lua_module_function_lines_get(module: *const Module, line: usize, name_len: *mut usize) -> *const c_char {
-    let module = &*module;
-    match module.get_function(line) {
+pub unsafe extern "C" fn lua_function_lines_error_get_message(error: *const LuaFunctionLinesError, message_len: *mut usize) -> *const c_char {
+    let message = match error.as_ref() {
+        Some(error) => &error.message,
+        None => return std::ptr::null(),
+    };
+    if !message_len.is_null() {
+        *message_len = message.as_bytes().len();
+    }
+    message.as_ptr()
+}
+
+/// Gets the zero-indexed line and column (in characters) where the error was found.
+/// Returns `false` and leaves `line` and `column` unchanged if the position is not known or `error` is `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn lua_function_lines_error_get_position(error: *const LuaFunctionLinesError, line: *mut usize, column: *mut usize) -> bool {
+    match error.as_ref().and_then(|error| error.position) {
+        Some((error_line, error_column)) => {
+            if !line.is_null() {
+                *line = error_line;
+            }
+            if !column.is_null() {
+                *column = error_column;
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Send the error to this function to be deallocated. Does nothing if `error` is `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn lua_function_lines_error_free(error: *mut LuaFunctionLinesError) {
+    if !error.is_null() {
+        catch_panic((), || drop(Box::from_raw(error)));
+    }
+}
+
+/// Gets name of the innermost function with some part on `line` (zero-indexed), including the lines
+/// of its `function` keyword and its `end`. Provides length of function name in `name_len`.
+/// Return value is not guaranteed to be zero-terminated. If the line does not correspond to a function
+/// or `module` is `NULL`, returns `NULL` and sets `name_len` to `(size_t) -1`.
+#[no_mangle]
+pub unsafe extern "C" fn lua_module_function_lines_get(module: *const LuaModuleFunctionLines, line: usize, name_len: *mut usize) -> *const c_char {
+    let name = match module.as_ref().map(|module| &module.0) {
+        Some(module) => catch_panic(None, || module.get_function(line)),
+        None => None,
+    };
+    match name {
         Some(name) if name.len() < !0 => {
-            if name_len != std::ptr::null::<usize>() as _ {
+            if !name_len.is_null() {
                 *name_len = name.len() as _;
             }
             name.as_ptr() as _
         },
         _ => {
-            if name_len != std::ptr::null::<usize>() as _ {
+            if !name_len.is_null() {
                 *name_len = !0;
             }
             std::ptr::null()
@@ -34,7 +238,90 @@ pub unsafe extern "C" fn lua_module_function_lines_get(module: *const Module, li
     }
 }
 
+/// Gets the number of functions in the module, including nested and anonymous functions, or 0 if `module` is `NULL`.
 #[no_mangle]
-pub unsafe extern "C" fn lua_module_function_lines_free(module: *mut Module) {
-    drop(Box::from_raw(module));
+pub unsafe extern "C" fn lua_module_function_lines_count(module: *const LuaModuleFunctionLines) -> usize {
+    match module.as_ref().map(|module| &module.0) {
+        Some(module) => module.function_record().len(),
+        None => 0,
+    }
 }
+
+/// Fills in `info` with the function at `index`, which is less than `lua_module_function_lines_count(module)`.
+/// Functions are ordered by their start; nested functions come after the function containing them.
+/// Returns `false` and leaves `info` unchanged if `index` is out of range or `module` or `info` is `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn lua_module_function_lines_function(module: *const LuaModuleFunctionLines, index: usize, info: *mut LuaFunctionInfo) -> bool {
+    let function = match module.as_ref().and_then(|module| module.0.function_record().get(index)) {
+        Some(function) if !info.is_null() => function,
+        _ => return false,
+    };
+    let kind = match function.kind {
+        FunctionKind::Local => LUA_FUNCTION_KIND_LOCAL,
+        FunctionKind::Global => LUA_FUNCTION_KIND_GLOBAL,
+        FunctionKind::Field => LUA_FUNCTION_KIND_FIELD,
+        FunctionKind::Method => LUA_FUNCTION_KIND_METHOD,
+        FunctionKind::Anonymous => LUA_FUNCTION_KIND_ANONYMOUS,
+    };
+    // Lines are zero-based in the C API.
+    *info = LuaFunctionInfo {
+        start_line: LINE_BASE.from_one_based(function.start),
+        end_line: LINE_BASE.from_one_based(function.end),
+        name: function.name.as_ptr() as _,
+        name_len: function.name.len(),
+        kind,
+    };
+    true
+}
+
+/// Gets the functions with some part on `line` (zero-indexed), from outermost to innermost, as indices
+/// for `lua_module_function_lines_function`. Several functions on the same line that are not nested in each other
+/// are all included. Writes at most `capacity` indices to `indices` and returns the total number of functions,
+/// so that a caller can retry with a larger array if the return value is greater than `capacity`.
+/// Returns 0 if `module` is `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn lua_module_function_lines_stack(module: *const LuaModuleFunctionLines, line: usize, indices: *mut usize, capacity: usize) -> usize {
+    let module = match module.as_ref().map(|module| &module.0) {
+        Some(module) => module,
+        None => return 0,
+    };
+    catch_panic(0, || {
+        let stack = module.get_function_stack_indices(line);
+        if !indices.is_null() {
+            for (i, &function) in stack.iter().take(capacity).enumerate() {
+                *indices.add(i) = function;
+            }
+        }
+        stack.len()
+    })
+}
+
+/// Gets the index of the innermost function containing the character at `line` and `column` (both zero-indexed,
+/// the column counted in characters). Returns `false` and leaves `index` unchanged if there is no such function
+/// or `module` is `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn lua_module_function_lines_at(module: *const LuaModuleFunctionLines, line: usize, column: usize, index: *mut usize) -> bool {
+    let module = match module.as_ref().map(|module| &module.0) {
+        Some(module) => module,
+        None => return false,
+    };
+    catch_panic(false, || match module.get_function_index_at(line, column + 1) {
+        Some(function) => {
+            if !index.is_null() {
+                *index = function;
+            }
+            true
+        }
+        None => false,
+    })
+}
+
+/// Send the object to this function to be deallocated. Does nothing if `module` is `NULL`.
+#[no_mangle]
+pub unsafe extern "C" fn lua_module_function_lines_free(module: *mut LuaModuleFunctionLines) {
+    if !module.is_null() {
+        catch_panic((), || drop(Box::from_raw(module)));
+    }
+}
+
+mod tests;
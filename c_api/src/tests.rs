@@ -0,0 +1,89 @@
+#![cfg(test)]
+
+use crate::*;
+
+#[test]
+fn header_is_up_to_date() {
+    // `build.rs` generates the header from `lib.rs`.
+    let generated = include_str!(concat!(env!("OUT_DIR"), "/lua_function_at_line.h"));
+    let header = include_str!("../include/lua_function_at_line.h");
+    assert!(
+        generated == header,
+        "include/lua_function_at_line.h is out of date: copy {}/lua_function_at_line.h to include",
+        env!("OUT_DIR"),
+    );
+}
+
+#[test]
+fn version_matches_header() {
+    assert_eq!(lua_function_at_line_version(), LUA_FUNCTION_AT_LINE_ABI_VERSION);
+}
+
+#[test]
+fn null_arguments_and_errors() {
+    unsafe {
+        let mut error = std::ptr::null_mut();
+        let module = lua_module_function_lines_new_with_error(std::ptr::null(), 1, &mut error);
+        assert!(module.is_null());
+        assert_eq!(lua_function_lines_error_get_kind(error), LUA_FUNCTION_LINES_ERROR_INVALID_ARGUMENT);
+        lua_function_lines_error_free(error);
+
+        let code = "local function f(";
+        let module = lua_module_function_lines_new_with_error(code.as_ptr() as _, code.len(), &mut error);
+        assert!(module.is_null());
+        assert_eq!(lua_function_lines_error_get_kind(error), LUA_FUNCTION_LINES_ERROR_PARSE);
+        let (mut line, mut column) = (0, 0);
+        assert!(lua_function_lines_error_get_position(error, &mut line, &mut column));
+        assert_eq!((line, column), (0, 17));
+        lua_function_lines_error_free(error);
+
+        let mut name_len = 0;
+        assert!(lua_module_function_lines_get(std::ptr::null(), 0, &mut name_len).is_null());
+        assert_eq!(name_len, !0);
+        assert_eq!(lua_module_function_lines_count(std::ptr::null()), 0);
+        assert!(!lua_module_function_lines_function(std::ptr::null(), 0, std::ptr::null_mut()));
+        assert_eq!(lua_function_lines_error_get_kind(std::ptr::null()), -1);
+        lua_module_function_lines_free(std::ptr::null_mut());
+        lua_function_lines_error_free(std::ptr::null_mut());
+    }
+}
+
+#[test]
+fn function_stack_and_function_at_column() {
+    unsafe {
+        let code = "local function f()\n  local g = function() end\nend\n";
+        let module = lua_module_function_lines_new(code.as_ptr() as _, code.len());
+        let mut indices = [!0; 1];
+        assert_eq!(lua_module_function_lines_stack(module, 1, indices.as_mut_ptr(), indices.len()), 2);
+        assert_eq!(indices, [0]);
+        let mut indices = [!0; 3];
+        assert_eq!(lua_module_function_lines_stack(module, 1, indices.as_mut_ptr(), indices.len()), 2);
+        assert_eq!(indices, [0, 1, !0]);
+        let mut index = !0;
+        assert!(lua_module_function_lines_at(module, 1, 12, &mut index));
+        assert_eq!(index, 1);
+        assert!(lua_module_function_lines_at(module, 1, 2, &mut index));
+        assert_eq!(index, 0);
+        assert!(!lua_module_function_lines_at(module, 3, 0, &mut index));
+        lua_module_function_lines_free(module);
+    }
+}
+
+#[test]
+fn zero_indexed_lines_include_the_end_line() {
+    unsafe {
+        let code = "local function f()\n  return 1\nend\n";
+        let module = lua_module_function_lines_new(code.as_ptr() as _, code.len());
+        let mut name_len = 0;
+        for line in 0..3 {
+            let name = lua_module_function_lines_get(module, line, &mut name_len);
+            assert!(!name.is_null(), "line {}", line);
+            assert_eq!(std::slice::from_raw_parts(name as *const u8, name_len), b"f");
+        }
+        assert!(lua_module_function_lines_get(module, 3, &mut name_len).is_null());
+        let mut info = std::mem::zeroed::<LuaFunctionInfo>();
+        assert!(lua_module_function_lines_function(module, 0, &mut info));
+        assert_eq!((info.start_line, info.end_line), (0, 2));
+        lua_module_function_lines_free(module);
+    }
+}
@@ -1,7 +1,12 @@
-// To compile:
+// To compile, linking the static library:
 // $ cargo build --release
-// $ cp target/release/liblua_function_at_line_c.so .
-// $ gcc test.c -o test -L. -Iinclude -llua_function_at_line_c -lm
+// $ cargo run --release --example pkg_config > target/release/lua_function_at_line_c.pc
+// $ export PKG_CONFIG_PATH=target/release
+// $ gcc test.c -o test $(pkg-config --cflags lua_function_at_line_c) -Wl,-Bstatic $(pkg-config --libs lua_function_at_line_c) -Wl,-Bdynamic $(pkg-config --libs-only-l --static lua_function_at_line_c)
+//
+// Or the shared library:
+// $ gcc test.c -o test $(pkg-config --cflags --libs lua_function_at_line_c) -lm
+// $ export LD_LIBRARY_PATH=target/release
 //
 // To run:
 // $ ./test some_lua_file.lua
@@ -32,11 +37,22 @@ char * read_file(const char * const path, size_t * len) {
     return buffer;
 }
 
+// Length of the line break at `p`, or 0 if there is none. Like Lua, this takes
+// "\n", "\r", "\r\n" and "\n\r" each as one line break.
+size_t line_break_len(const char * p, const char * end) {
+    if (p >= end || (*p != '\n' && *p != '\r')) return 0;
+    if (p + 1 < end && (p[1] == '\n' || p[1] == '\r') && p[1] != *p) return 2;
+    return 1;
+}
+
 size_t count_lines(const char * str, size_t len) {
     size_t lines = 1;
     const char * end = str + len;
-    const char * line_break = str;
-    while (line_break < end && (line_break = memchr(line_break, '\n', end - line_break))) ++lines, ++line_break;
+    for (const char * p = str; p < end; ) {
+        size_t break_len = line_break_len(p, end);
+        if (break_len != 0) ++lines, p += break_len;
+        else ++p;
+    }
     return lines;
 }
 
@@ -51,27 +67,27 @@ string_ref * get_lines(const char * str, size_t len, size_t * out_line_count) {
     if (lines == NULL) exit(1);
     if (out_line_count != NULL) *out_line_count = line_count;
     const char * end = str + len;
-    const char * line_start = str;
+    // The last line is empty if the code ends in a line break.
     string_ref * cur_line = lines;
-    while (line_start != NULL && line_start < end && cur_line < lines + line_count) {
-        const char * next_line_break = memchr(line_start, '\n', end - line_start);
-        size_t line_len = (next_line_break != NULL) ? next_line_break - line_start : end - line_start;
-        // Strip CR in CRLF.
-        if (next_line_break != NULL && next_line_break - 1 >= str && *(next_line_break - 1) == '\r') {
-            --line_len;
+    cur_line->ptr = str;
+    for (const char * p = str; p < end; ) {
+        size_t break_len = line_break_len(p, end);
+        if (break_len == 0) {
+            ++p;
+            continue;
         }
-        cur_line->ptr = line_start;
-        cur_line->len = line_len;
-        if (next_line_break == NULL) break;
-        line_start = next_line_break + 1;
+        cur_line->len = p - cur_line->ptr;
+        p += break_len;
         ++cur_line;
+        cur_line->ptr = p;
     }
+    cur_line->len = end - cur_line->ptr;
     return lines;
 }
 
 void get_function_names(lua_module_function_lines * module, string_ref * const names, size_t len) {
     string_ref * cur_name = names;
-    // This assumes that lines in full_moon are zero-based.
+    // Lines are zero-indexed in the C API.
     size_t line = 0;
     while (line < len) {
         size_t name_len = -1;
@@ -82,10 +98,57 @@ void get_function_names(lua_module_function_lines * module, string_ref * const n
     }
 }
 
+void print_function_name(lua_module_function_lines * module, size_t index) {
+    lua_function_info info;
+    if (lua_module_function_lines_function(module, index, &info)) {
+        printf("%.*s", (int) info.name_len, info.name);
+    }
+}
+
+// For each line in a function, prints the functions enclosing it, outermost first,
+// and the innermost function at the first non-blank character of the line.
+void show_function_stacks(lua_module_function_lines * module, const string_ref * lines, size_t line_count) {
+    size_t capacity = 4;
+    size_t * indices = malloc(capacity * sizeof (size_t));
+    if (indices == NULL) return;
+    for (size_t line = 0; line < line_count; ++line) {
+        size_t count = lua_module_function_lines_stack(module, line, indices, capacity);
+        if (count > capacity) {
+            size_t * larger = realloc(indices, count * sizeof (size_t));
+            if (larger == NULL) break;
+            indices = larger, capacity = count;
+            lua_module_function_lines_stack(module, line, indices, capacity);
+        }
+        if (count == 0) continue;
+        printf("%zu:", line + 1);
+        for (size_t i = 0; i < count; ++i) {
+            printf(i == 0 ? " " : " > ");
+            print_function_name(module, indices[i]);
+        }
+        size_t column = 0;
+        while (column < lines[line].len && (lines[line].ptr[column] == ' ' || lines[line].ptr[column] == '\t')) ++column;
+        size_t index = 0;
+        if (lua_module_function_lines_at(module, line, column, &index)) {
+            printf(" (at column %zu: ", column + 1);
+            print_function_name(module, index);
+            printf(")");
+        }
+        printf("\n");
+    }
+    free(indices);
+}
+
 void show_lines_with_function_names(const char * lua_code, size_t lua_code_len) {
-    lua_module_function_lines * module = lua_module_function_lines_new(lua_code, lua_code_len);
+    lua_function_lines_error * error = NULL;
+    lua_module_function_lines * module = lua_module_function_lines_new_with_error(lua_code, lua_code_len, &error);
     if (!module) {
-        printf("failed to parse Lua code:\n%s\n", lua_code);
+        size_t line = 0, column = 0;
+        printf("failed to parse Lua code: ");
+        if (lua_function_lines_error_get_position(error, &line, &column)) {
+            printf("%zu:%zu: ", line + 1, column + 1);
+        }
+        printf("%s\n", lua_function_lines_error_get_message(error, NULL));
+        lua_function_lines_error_free(error);
         return;
     }
     size_t line_count = 0;
@@ -123,6 +186,27 @@ void show_lines_with_function_names(const char * lua_code, size_t lua_code_len)
             (int) cur_line->len, cur_line->ptr
         );
     }
+
+    // List the functions, like `examples/parse.rs`.
+    size_t function_count = lua_module_function_lines_count(module);
+    lua_function_info info;
+    for (size_t i = 0; i < function_count; ++i) {
+        if (lua_module_function_lines_function(module, i, &info) && info.name_len > max_function_name_len) {
+            max_function_name_len = info.name_len;
+        }
+    }
+    for (size_t i = 0; i < function_count; ++i) {
+        if (!lua_module_function_lines_function(module, i, &info)) break;
+        printf(
+            "%*s%.*s %-3zu..%-3zu\n",
+            (int) (max_function_name_len - info.name_len), "",
+            (int) info.name_len, info.name,
+            info.start_line, info.end_line
+        );
+    }
+
+    show_function_stacks(module, lines, line_count);
+
     free_all:
     lua_module_function_lines_free(module);
     free(function_names);
@@ -134,6 +218,10 @@ int main(int argc, char * * argv) {
         fprintf(stderr, "expected Lua file name\n");
         return 1;
     }
+    if (lua_function_at_line_version() != LUA_FUNCTION_AT_LINE_ABI_VERSION) {
+        fprintf(stderr, "library has ABI version %u, expected %u\n", lua_function_at_line_version(), LUA_FUNCTION_AT_LINE_ABI_VERSION);
+        return 1;
+    }
     const char * const lua_path = argv[1];
     size_t lua_code_len = 0;
     char * const lua_code = read_file(lua_path, &lua_code_len);
@@ -0,0 +1,28 @@
+use lua_function_at_line::{bytecode::Chunk, Module};
+
+fn main() {
+    let mut args = std::env::args_os().skip(1);
+    let chunk_file = args.next().expect("supply binary chunk file name");
+    let source_file = args.next().expect("supply Lua source file name");
+    let bytes = std::fs::read(&chunk_file).expect("failed to read binary chunk");
+    let code = std::fs::read_to_string(&source_file).expect("failed to read Lua source");
+    let chunk = Chunk::read(&bytes).unwrap_or_else(|e| {
+        eprintln!("invalid binary chunk: {}", e);
+        std::process::exit(1);
+    });
+    let module = Module::new(&code).unwrap_or_else(|| {
+        eprintln!("failed to parse Lua source");
+        std::process::exit(1);
+    });
+    for (prototype, function) in module.match_prototypes(&chunk.main) {
+        let name = match function {
+            Some(function) => function.name.as_str(),
+            None if prototype.is_main() => "<chunk>",
+            None => "<unknown>",
+        };
+        println!(
+            "{:<3}..{:<3} {}",
+            prototype.line_defined, prototype.last_line_defined, name
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use lua_function_at_line::{coverage::LuaCovStats, Module};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let file = args.next().expect("supply Lua file name");
+    let stats_file = args.next().unwrap_or_else(|| "luacov.stats.out".into());
+    let code = std::fs::read_to_string(&file).expect("failed to read Lua file");
+    let stats = std::fs::read_to_string(&stats_file).expect("failed to read LuaCov stats");
+    let stats = LuaCovStats::parse(&stats).unwrap_or_else(|e| {
+        eprintln!("{}: {}", stats_file, e);
+        std::process::exit(1);
+    });
+    let hits = stats.hits(&file).unwrap_or_else(|| {
+        eprintln!("{} not found in {}", file, stats_file);
+        std::process::exit(1);
+    });
+    let module = Module::new(&code).unwrap_or_else(|| {
+        eprintln!("failed to parse {}", file);
+        std::process::exit(1);
+    });
+    let coverage = module.coverage(hits);
+    let max_function_name = coverage.iter().map(|function| function.name().len()).max();
+    for function in coverage {
+        let ratio = match function.ratio() {
+            Some(ratio) => format!("{:.1}%", ratio * 100.0),
+            None => "-".into(),
+        };
+        println!(
+            "{: >width$} {: >3}/{: <3} {: >6}{}",
+            function.name(),
+            function.hit_count(),
+            function.executable_count(),
+            ratio,
+            if function.called() { "" } else { "  never called" },
+            width = max_function_name.unwrap_or(0)
+        );
+    }
+}
@@ -2,7 +2,7 @@ use lua_function_at_line::{FunctionNameLine, Module};
 use full_moon::parse;
 
 fn main() {
-    let file = std::env::args_os().skip(1).next().expect("supply file name");
+    let file = std::env::args_os().nth(1).expect("supply file name");
     let code = std::fs::read_to_string(&file).expect("failed to read file");
     let module = Module::new(&code).unwrap_or_else(|| {
         // Show error message ignored by FunctionFinder.
@@ -14,7 +14,7 @@ fn main() {
     for (i, line) in code.lines().enumerate() {
         println!("{: <5}{: >width$}  {}", i, module.get_function(i).unwrap_or("<chunk>"), line, width = max_function_name.unwrap_or(0));
     }
-    for FunctionNameLine { start, end, name } in functions {
+    for FunctionNameLine { start, end, name, .. } in functions {
         println!("{: >width$} {:<3}..{:<3}", name, start, end, width = max_function_name.unwrap_or(0))
     }
 }
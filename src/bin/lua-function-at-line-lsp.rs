@@ -0,0 +1,15 @@
+// Language server for Lua document symbols, folding ranges and enclosing functions over stdio.
+
+fn main() {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    match lua_function_at_line::lsp::run(stdin.lock(), stdout.lock()) {
+        Ok(true) => {}
+        // exit without shutdown
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("lua-function-at-line-lsp: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,399 @@
+use crate::{FunctionNameLine, Module};
+
+// Reader for precompiled chunks produced by `luac` (or `string.dump`) for Lua 5.1 and 5.4.
+// Only the prototype tree and line information are kept; instructions and constants are skipped.
+
+const SIGNATURE: &[u8] = b"\x1bLua";
+// Lua 5.4 `LUAC_DATA`, used to detect conversion errors
+const LUAC_DATA: &[u8] = b"\x19\x93\r\n\x1a\n";
+const LUAC_INT: u64 = 0x5678;
+const LUAC_NUM: f64 = 370.5;
+// Lua does not compile functions nested more deeply than this (`LUAI_MAXCCALLS`),
+// and the prototypes are read recursively.
+pub(crate) const MAX_DEPTH: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LuaVersion {
+    Lua51,
+    Lua54,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prototype {
+    // chunk name such as `@file.lua`, inherited from the enclosing prototype if not recorded
+    pub source: Option<String>,
+    // 0 for the main chunk
+    pub line_defined: usize,
+    pub last_line_defined: usize,
+    pub prototypes: Vec<Prototype>,
+}
+
+impl Prototype {
+    pub fn is_main(&self) -> bool {
+        self.line_defined == 0
+    }
+
+    // This prototype followed by all nested prototypes, in the order in which they appear in the source.
+    pub fn iter(&self) -> impl Iterator<Item = &Prototype> {
+        let mut stack = vec![self];
+        std::iter::from_fn(move || {
+            let prototype = stack.pop()?;
+            stack.extend(prototype.prototypes.iter().rev());
+            Some(prototype)
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub version: LuaVersion,
+    pub main: Prototype,
+}
+
+impl Chunk {
+    pub fn read(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader::new(bytes);
+        if reader.take(SIGNATURE.len())? != SIGNATURE {
+            return Err("not a binary chunk".into());
+        }
+        let version = match reader.byte()? {
+            0x51 => LuaVersion::Lua51,
+            0x54 => LuaVersion::Lua54,
+            version => {
+                return Err(format!(
+                    "unsupported Lua version {}.{}",
+                    version >> 4,
+                    version & 0xf
+                ))
+            }
+        };
+        if reader.byte()? != 0 {
+            return Err("unsupported chunk format".into());
+        }
+        let main = match version {
+            LuaVersion::Lua51 => {
+                reader.read_header_51()?;
+                reader.read_function_51(None, 0)?
+            }
+            LuaVersion::Lua54 => {
+                reader.read_header_54()?;
+                // number of upvalues of the main closure
+                reader.byte()?;
+                reader.read_function_54(None, 0)?
+            }
+        };
+        Ok(Chunk { version, main })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    little_endian: bool,
+    int_size: usize,
+    size_t_size: usize,
+    instruction_size: usize,
+    integer_size: usize,
+    number_size: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader {
+            bytes,
+            position: 0,
+            little_endian: true,
+            int_size: 4,
+            size_t_size: 8,
+            instruction_size: 4,
+            integer_size: 8,
+            number_size: 8,
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .position
+            .checked_add(len)
+            .and_then(|end| self.bytes.get(self.position..end))
+            .ok_or_else(|| format!("truncated chunk at byte {}", self.position))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, count: usize, size: usize) -> Result<(), String> {
+        let len = count
+            .checked_mul(size)
+            .ok_or_else(|| format!("invalid length at byte {}", self.position))?;
+        self.take(len).map(|_| ())
+    }
+
+    fn check_depth(&self, depth: usize) -> Result<(), String> {
+        if depth > MAX_DEPTH {
+            return Err(format!(
+                "functions nested more than {} levels deep at byte {}",
+                MAX_DEPTH, self.position
+            ));
+        }
+        Ok(())
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn size(&mut self) -> Result<usize, String> {
+        let size = self.byte()? as usize;
+        if size == 0 || size > 8 {
+            return Err(format!("unsupported type size {}", size));
+        }
+        Ok(size)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> u64 {
+        let fold = |value: u64, &byte: &u8| (value << 8) | byte as u64;
+        if self.little_endian {
+            bytes.iter().rev().fold(0, fold)
+        } else {
+            bytes.iter().fold(0, fold)
+        }
+    }
+
+    // Fixed-size unsigned integer in the chunk's byte order.
+    fn uint(&mut self, size: usize) -> Result<u64, String> {
+        let bytes = self.take(size)?;
+        Ok(self.decode(bytes))
+    }
+
+    fn usize(&mut self, size: usize) -> Result<usize, String> {
+        let position = self.position;
+        let value = self.uint(size)?;
+        if value > i32::MAX as u64 {
+            return Err(format!("invalid count {} at byte {}", value, position));
+        }
+        Ok(value as usize)
+    }
+
+    fn int_51(&mut self) -> Result<usize, String> {
+        self.usize(self.int_size)
+    }
+
+    // Lua 5.4 integers are stored most significant group first, seven bits per byte,
+    // with the high bit set on the last byte.
+    fn varint(&mut self) -> Result<usize, String> {
+        let position = self.position;
+        let mut value: usize = 0;
+        loop {
+            let byte = self.byte()?;
+            if value > i32::MAX as usize >> 7 {
+                return Err(format!("integer overflow at byte {}", position));
+            }
+            value = (value << 7) | (byte & 0x7f) as usize;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn string(&mut self, len: usize) -> Result<String, String> {
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn read_header_51(&mut self) -> Result<(), String> {
+        self.little_endian = match self.byte()? {
+            0 => false,
+            1 => true,
+            _ => return Err("invalid endianness flag".into()),
+        };
+        self.int_size = self.size()?;
+        self.size_t_size = self.size()?;
+        self.instruction_size = self.size()?;
+        self.number_size = self.size()?;
+        // whether `lua_Number` is an integral type
+        self.byte()?;
+        Ok(())
+    }
+
+    fn read_header_54(&mut self) -> Result<(), String> {
+        if self.take(LUAC_DATA.len())? != LUAC_DATA {
+            return Err("corrupted chunk".into());
+        }
+        self.instruction_size = self.size()?;
+        self.integer_size = self.size()?;
+        self.number_size = self.size()?;
+        // The byte order is not recorded, but it can be deduced from the test integer.
+        let test_int = self.take(self.integer_size)?;
+        self.little_endian = test_int.first() == Some(&(LUAC_INT as u8));
+        if self.decode(test_int) != LUAC_INT {
+            return Err("integer format mismatch".into());
+        }
+        if self.number_size != 8 || f64::from_bits(self.uint(8)?) != LUAC_NUM {
+            return Err("float format mismatch".into());
+        }
+        Ok(())
+    }
+
+    fn string_51(&mut self) -> Result<Option<String>, String> {
+        // length includes the terminating null byte; 0 means no string
+        let len = self.usize(self.size_t_size)?;
+        if len == 0 {
+            return Ok(None);
+        }
+        let string = self.string(len - 1)?;
+        self.byte()?;
+        Ok(Some(string))
+    }
+
+    fn read_function_51(&mut self, parent_source: Option<&str>, depth: usize) -> Result<Prototype, String> {
+        self.check_depth(depth)?;
+        let source = self
+            .string_51()?
+            .or_else(|| parent_source.map(String::from));
+        let line_defined = self.int_51()?;
+        let last_line_defined = self.int_51()?;
+        // upvalue count, parameter count, vararg flag, maximum stack size
+        self.skip(4, 1)?;
+        let code_len = self.int_51()?;
+        self.skip(code_len, self.instruction_size)?;
+        for _ in 0..self.int_51()? {
+            match self.byte()? {
+                // nil
+                0 => {}
+                // boolean
+                1 => self.skip(1, 1)?,
+                // number
+                3 => self.skip(1, self.number_size)?,
+                // string
+                4 => {
+                    self.string_51()?;
+                }
+                tag => return Err(format!("invalid constant type {}", tag)),
+            }
+        }
+        let mut prototypes = Vec::new();
+        for _ in 0..self.int_51()? {
+            prototypes.push(self.read_function_51(source.as_deref(), depth + 1)?);
+        }
+        // line info
+        let line_info_len = self.int_51()?;
+        self.skip(line_info_len, self.int_size)?;
+        // local variable names and ranges
+        for _ in 0..self.int_51()? {
+            self.string_51()?;
+            self.skip(2, self.int_size)?;
+        }
+        // upvalue names
+        for _ in 0..self.int_51()? {
+            self.string_51()?;
+        }
+        Ok(Prototype {
+            source,
+            line_defined,
+            last_line_defined,
+            prototypes,
+        })
+    }
+
+    fn string_54(&mut self) -> Result<Option<String>, String> {
+        // length plus one; 0 means no string
+        match self.varint()? {
+            0 => Ok(None),
+            len => self.string(len - 1).map(Some),
+        }
+    }
+
+    fn read_function_54(&mut self, parent_source: Option<&str>, depth: usize) -> Result<Prototype, String> {
+        self.check_depth(depth)?;
+        let source = self
+            .string_54()?
+            .or_else(|| parent_source.map(String::from));
+        let line_defined = self.varint()?;
+        let last_line_defined = self.varint()?;
+        // parameter count, vararg flag, maximum stack size
+        self.skip(3, 1)?;
+        let code_len = self.varint()?;
+        self.skip(code_len, self.instruction_size)?;
+        for _ in 0..self.varint()? {
+            match self.byte()? {
+                // nil, false, true
+                0x00 | 0x01 | 0x11 => {}
+                // integer
+                0x03 => self.skip(1, self.integer_size)?,
+                // float
+                0x13 => self.skip(1, self.number_size)?,
+                // short and long string
+                0x04 | 0x14 => {
+                    self.string_54()?;
+                }
+                tag => return Err(format!("invalid constant type {}", tag)),
+            }
+        }
+        // upvalues: in-stack flag, index, kind
+        let upvalue_count = self.varint()?;
+        self.skip(upvalue_count, 3)?;
+        let mut prototypes = Vec::new();
+        for _ in 0..self.varint()? {
+            prototypes.push(self.read_function_54(source.as_deref(), depth + 1)?);
+        }
+        // relative line info
+        let line_info_len = self.varint()?;
+        self.skip(line_info_len, 1)?;
+        // absolute line info: instruction index and line
+        for _ in 0..self.varint()? {
+            self.varint()?;
+            self.varint()?;
+        }
+        // local variable names and ranges
+        for _ in 0..self.varint()? {
+            self.string_54()?;
+            self.varint()?;
+            self.varint()?;
+        }
+        // upvalue names
+        for _ in 0..self.varint()? {
+            self.string_54()?;
+        }
+        Ok(Prototype {
+            source,
+            line_defined,
+            last_line_defined,
+            prototypes,
+        })
+    }
+}
+
+impl Module {
+    // Pairs every prototype in the tree under `main` with the function in `function_record`
+    // that it was compiled from. The main chunk and functions that were not found get `None`.
+    // Lua records the line of the parameter list as the start of a function,
+    // which may come after the line where the function statement begins.
+    pub fn match_prototypes<'p>(
+        &self,
+        main: &'p Prototype,
+    ) -> Vec<(&'p Prototype, Option<&FunctionNameLine>)> {
+        let functions = self.function_record();
+        // Both trees are in source order, so each match comes after the previous one.
+        let mut next = 0;
+        main.iter()
+            .map(|prototype| {
+                if prototype.is_main() {
+                    return (prototype, None);
+                }
+                let found = functions[next..]
+                    .iter()
+                    .position(|function| {
+                        function.end == prototype.last_line_defined
+                            && function.start <= prototype.line_defined
+                    })
+                    .map(|i| next + i);
+                match found {
+                    Some(i) => {
+                        next = i + 1;
+                        (prototype, Some(&functions[i]))
+                    }
+                    None => (prototype, None),
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{FunctionNameLine, Module};
+
+// Hit counts from a LuaCov stats file (`luacov.stats.out`).
+// For each file, LuaCov writes a header line `<max line>:<file name>`
+// followed by a line with the hit count of every line from 1 to max line.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LuaCovStats {
+    files: Vec<(String, Vec<u64>)>,
+}
+
+impl LuaCovStats {
+    pub fn parse(stats: &str) -> Result<Self, String> {
+        let mut files = Vec::new();
+        let mut lines = stats.lines().enumerate();
+        while let Some((i, header)) = lines.next() {
+            if header.trim().is_empty() {
+                continue;
+            }
+            let (max, name) = header
+                .split_once(':')
+                .ok_or_else(|| format!("line {}: expected `<max line>:<file name>`", i + 1))?;
+            let max: usize = max
+                .trim()
+                .parse()
+                .map_err(|e| format!("line {}: invalid line count: {}", i + 1, e))?;
+            let (i, counts) = lines
+                .next()
+                .ok_or_else(|| format!("line {}: missing hit counts for {}", i + 2, name))?;
+            let hits = counts
+                .split_whitespace()
+                .map(|count| {
+                    count
+                        .parse()
+                        .map_err(|e| format!("line {}: invalid hit count: {}", i + 1, e))
+                })
+                .collect::<Result<Vec<u64>, _>>()?;
+            if hits.len() != max {
+                return Err(format!(
+                    "line {}: expected {} hit counts, found {}",
+                    i + 1,
+                    max,
+                    hits.len()
+                ));
+            }
+            files.push((name.to_string(), hits));
+        }
+        Ok(Self { files })
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = (&str, &[u64])> {
+        self.files
+            .iter()
+            .map(|(name, hits)| (name.as_str(), hits.as_slice()))
+    }
+
+    // Hit counts for a file, indexed by line number minus one.
+    // LuaCov records file names the way they were passed to `loadfile` or `require`,
+    // so a file whose path ends with `path` (or vice versa) also matches.
+    pub fn hits(&self, path: &str) -> Option<&[u64]> {
+        fn normalize(path: &str) -> String {
+            let path = path.replace('\\', "/");
+            path.trim_start_matches("./").to_string()
+        }
+
+        fn is_suffix(path: &str, suffix: &str) -> bool {
+            path.strip_suffix(suffix)
+                .is_some_and(|rest| rest.is_empty() || rest.ends_with('/'))
+        }
+
+        let path = normalize(path);
+        self.files()
+            .find(|(name, _)| normalize(name) == path)
+            .or_else(|| {
+                self.files().find(|(name, _)| {
+                    let name = normalize(name);
+                    is_suffix(&name, &path) || is_suffix(&path, &name)
+                })
+            })
+            .map(|(_, hits)| hits)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionCoverage<'a> {
+    // `None` for the main chunk
+    pub function: Option<&'a FunctionNameLine>,
+    pub hit_lines: Vec<usize>,
+    pub missed_lines: Vec<usize>,
+}
+
+impl<'a> FunctionCoverage<'a> {
+    fn new(
+        function: Option<&'a FunctionNameLine>,
+        executable_lines: &[usize],
+        hits: &[u64],
+    ) -> Self {
+        let (hit_lines, missed_lines) = executable_lines.iter().partition(|&&line| {
+            line.checked_sub(1)
+                .and_then(|i| hits.get(i))
+                .is_some_and(|&count| count > 0)
+        });
+        FunctionCoverage {
+            function,
+            hit_lines,
+            missed_lines,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.function.map_or("<chunk>", |function| &function.name)
+    }
+
+    pub fn executable_count(&self) -> usize {
+        self.hit_lines.len() + self.missed_lines.len()
+    }
+
+    pub fn hit_count(&self) -> usize {
+        self.hit_lines.len()
+    }
+
+    // A function with an empty body has no executable lines,
+    // so it is never considered to have been called.
+    pub fn called(&self) -> bool {
+        !self.hit_lines.is_empty()
+    }
+
+    // Proportion of executable lines that were hit, or `None` if there are none.
+    pub fn ratio(&self) -> Option<f64> {
+        match self.executable_count() {
+            0 => None,
+            total => Some(self.hit_count() as f64 / total as f64),
+        }
+    }
+}
+
+impl Module {
+    // Coverage of the main chunk followed by every function in `function_record` order.
+    // `hits` is indexed by line number minus one, as returned by `LuaCovStats::hits`.
+    pub fn coverage(&self, hits: &[u64]) -> Vec<FunctionCoverage<'_>> {
+        std::iter::once(FunctionCoverage::new(None, self.executable_lines(), hits))
+            .chain(self.function_record().iter().map(|function| {
+                FunctionCoverage::new(Some(function), &function.executable_lines, hits)
+            }))
+            .collect()
+    }
+}
@@ -0,0 +1,148 @@
+// Compares the functions of two versions of a module.
+
+use crate::{FunctionNameLine, Module};
+use std::{collections::HashMap, hash::Hash};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ChangeKind {
+    Unchanged,
+    // the body is the same, but the function is in a different order among the others
+    Moved,
+    // the body is the same, but the name is different
+    Renamed,
+    Modified,
+    Added,
+    Removed,
+}
+
+impl ChangeKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChangeKind::Unchanged => "unchanged",
+            ChangeKind::Moved => "moved",
+            ChangeKind::Renamed => "renamed",
+            ChangeKind::Modified => "modified",
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+        }
+    }
+}
+
+// `old` is `None` for added functions, `new` for removed ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FunctionChange<'a> {
+    pub kind: ChangeKind,
+    pub old: Option<&'a FunctionNameLine>,
+    pub new: Option<&'a FunctionNameLine>,
+}
+
+// Indices into `sequence` that are not part of one of its longest increasing subsequences.
+fn out_of_order(sequence: &[usize]) -> Vec<bool> {
+    // `tails[k]` is the index of the smallest last element of an increasing subsequence
+    // of length `k + 1`, `previous[i]` the element before `i` in such a subsequence.
+    let mut tails: Vec<usize> = Vec::new();
+    let mut previous = vec![None; sequence.len()];
+    for (i, &value) in sequence.iter().enumerate() {
+        let k = tails.partition_point(|&tail| sequence[tail] < value);
+        previous[i] = k.checked_sub(1).map(|k| tails[k]);
+        if k == tails.len() {
+            tails.push(i);
+        } else {
+            tails[k] = i;
+        }
+    }
+    let mut moved = vec![true; sequence.len()];
+    let mut i = tails.last().copied();
+    while let Some(j) = i {
+        moved[j] = false;
+        i = previous[j];
+    }
+    moved
+}
+
+// Matches the functions that are not matched yet and have the same key,
+// in order of appearance when several functions have the same key.
+fn match_by<'a, K: Eq + Hash>(
+    old: &'a [FunctionNameLine],
+    new: &'a [FunctionNameLine],
+    matches: &mut [Option<usize>],
+    matched: &mut [bool],
+    key: impl Fn(&'a FunctionNameLine) -> K,
+) {
+    let mut by_key: HashMap<K, Vec<usize>> = HashMap::new();
+    for (i, function) in old.iter().enumerate().rev() {
+        if !matched[i] {
+            by_key.entry(key(function)).or_default().push(i);
+        }
+    }
+    for (j, function) in new.iter().enumerate() {
+        if matches[j].is_none() {
+            if let Some(i) = by_key.get_mut(&key(function)).and_then(Vec::pop) {
+                matches[j] = Some(i);
+                matched[i] = true;
+            }
+        }
+    }
+}
+
+// Matches the functions of `old` and `new` and reports what happened to each of them.
+// Functions with the same name and fingerprint are matched first, then the remaining ones
+// by name, so that modified functions are found, and then by fingerprint, so that renamed
+// functions are found. Matching unchanged functions first keeps anonymous functions,
+// which all have the same name, from being paired with the wrong ones.
+// Changes are in the order of the functions in `new`,
+// followed by the removed functions in the order of `old`.
+pub fn diff<'a>(old: &'a Module, new: &'a Module) -> Vec<FunctionChange<'a>> {
+    let (old, new) = (old.function_record(), new.function_record());
+    // index in `old` of the function matched with each function in `new`
+    let mut matches: Vec<Option<usize>> = vec![None; new.len()];
+    let mut matched = vec![false; old.len()];
+    match_by(old, new, &mut matches, &mut matched, |function| {
+        (function.name.as_str(), function.fingerprint)
+    });
+    match_by(old, new, &mut matches, &mut matched, |function| function.name.as_str());
+    match_by(old, new, &mut matches, &mut matched, |function| function.fingerprint);
+
+    let order = matches.iter().flatten().copied().collect::<Vec<_>>();
+    let mut moved = out_of_order(&order).into_iter();
+
+    let mut changes = Vec::with_capacity(new.len());
+    for (j, function) in new.iter().enumerate() {
+        let change = match matches[j] {
+            Some(i) => {
+                let was_moved = moved.next().unwrap_or(false);
+                let kind = if old[i].fingerprint != function.fingerprint {
+                    ChangeKind::Modified
+                } else if old[i].name != function.name {
+                    ChangeKind::Renamed
+                } else if was_moved {
+                    ChangeKind::Moved
+                } else {
+                    ChangeKind::Unchanged
+                };
+                FunctionChange {
+                    kind,
+                    old: Some(&old[i]),
+                    new: Some(function),
+                }
+            }
+            None => FunctionChange {
+                kind: ChangeKind::Added,
+                old: None,
+                new: Some(function),
+            },
+        };
+        changes.push(change);
+    }
+    changes.extend(
+        old.iter()
+            .zip(matched)
+            .filter(|(_, matched)| !matched)
+            .map(|(function, _)| FunctionChange {
+                kind: ChangeKind::Removed,
+                old: Some(function),
+                new: None,
+            }),
+    );
+    changes
+}
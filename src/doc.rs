@@ -0,0 +1,213 @@
+// Doc comments in the styles of LDoc and of EmmyLua (the Lua language server).
+//
+// A doc comment is the block of comments on the lines right above a function,
+// starting with a `---` comment or a `--[[--` comment. Tags are lines starting with `@`:
+// `@param name [type] description`, `@return [type] description`,
+// `@tparam type name description` and `@treturn type description`.
+// When the tag directly follows the dashes, as in `---@param`, it is taken to be EmmyLua,
+// where `@param` and `@return` are followed by a type, otherwise LDoc, where they are not.
+
+use crate::{start_line, Parameter};
+use full_moon::tokenizer::{TokenReference, TokenType};
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct DocComment {
+    // text before the first tag, without the comment markers
+    pub description: String,
+    pub params: Vec<DocParam>,
+    pub returns: Vec<DocReturn>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct DocParam {
+    pub name: String,
+    pub type_name: Option<String>,
+    // `name?` in EmmyLua or `@param[opt]` in LDoc
+    pub optional: bool,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct DocReturn {
+    pub type_name: Option<String>,
+    pub description: String,
+}
+
+// A difference between the documented parameters and the parameters of a function.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum ParameterMismatch {
+    // a parameter without a `@param` or `@tparam` tag
+    Undocumented(String),
+    // a `@param` or `@tparam` tag for a name that is not a parameter
+    NotAParameter(String),
+}
+
+// Which tag a line of text after a tag continues.
+enum Continued {
+    Description,
+    Param,
+    Return,
+    Other,
+}
+
+// Splits off the first word of `text`, where brackets can contain spaces,
+// as in the EmmyLua type `table<string, number>`.
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    let mut depth = 0usize;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' | '[' | '{' | '<' => depth += 1,
+            ')' | ']' | '}' | '>' => depth = depth.saturating_sub(1),
+            c if c.is_whitespace() && depth == 0 => return (&text[..i], text[i..].trim_start()),
+            _ => {}
+        }
+    }
+    (text, "")
+}
+
+fn append_line(text: &mut String, line: &str) {
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    text.push_str(line);
+}
+
+impl DocComment {
+    // Parses the text of a doc comment, given as lines with the comment markers removed.
+    // `emmy_lua` tells, for each line, whether it is in the EmmyLua style.
+    fn parse<'a>(lines: impl IntoIterator<Item = (&'a str, bool)>) -> Self {
+        let mut doc = DocComment::default();
+        let mut continued = Continued::Description;
+        for (line, emmy_lua) in lines {
+            let tag = match line.strip_prefix('@') {
+                Some(tag) => tag,
+                None => {
+                    let text = match continued {
+                        Continued::Description => &mut doc.description,
+                        Continued::Param => &mut doc.params.last_mut().unwrap().description,
+                        Continued::Return => &mut doc.returns.last_mut().unwrap().description,
+                        Continued::Other => continue,
+                    };
+                    append_line(text, line.trim_end());
+                    continue;
+                }
+            };
+            let name_end = tag
+                .find(|c: char| c.is_whitespace() || c == '[')
+                .unwrap_or(tag.len());
+            let (name, rest) = tag.split_at(name_end);
+            // LDoc modifiers, as in `@param[opt]`
+            let (modifiers, rest) = match rest.strip_prefix('[') {
+                Some(rest) => rest.split_once(']').unwrap_or((rest, "")),
+                None => ("", rest),
+            };
+            let optional = modifiers.split(',').any(|modifier| modifier.trim() == "opt");
+            continued = match name {
+                "param" | "tparam" => {
+                    let (type_name, rest) = if name == "tparam" {
+                        let (type_name, rest) = split_word(rest);
+                        (Some(type_name), rest)
+                    } else {
+                        (None, rest)
+                    };
+                    let (param_name, rest) = split_word(rest);
+                    let (type_name, rest) = if emmy_lua && type_name.is_none() {
+                        let (type_name, rest) = split_word(rest);
+                        (Some(type_name), rest)
+                    } else {
+                        (type_name, rest)
+                    };
+                    let (param_name, optional) = match param_name.strip_suffix('?') {
+                        Some(param_name) => (param_name, true),
+                        None => (param_name, optional),
+                    };
+                    doc.params.push(DocParam {
+                        name: param_name.to_string(),
+                        type_name: type_name.filter(|name| !name.is_empty()).map(str::to_string),
+                        optional,
+                        description: rest.trim_end().to_string(),
+                    });
+                    Continued::Param
+                }
+                "return" | "treturn" => {
+                    let (type_name, rest) = if name == "treturn" || emmy_lua {
+                        let (type_name, rest) = split_word(rest);
+                        (Some(type_name), rest)
+                    } else {
+                        (None, rest.trim_start())
+                    };
+                    doc.returns.push(DocReturn {
+                        type_name: type_name.filter(|name| !name.is_empty()).map(str::to_string),
+                        description: rest.trim_end().to_string(),
+                    });
+                    Continued::Return
+                }
+                _ => Continued::Other,
+            };
+        }
+        doc.description = doc.description.trim().to_string();
+        doc
+    }
+
+    // Parameters of the function without documentation and documented parameters that it does not have.
+    // An implicit `self` parameter does not have to be documented.
+    pub fn check_parameters(&self, parameters: &[Parameter]) -> Vec<ParameterMismatch> {
+        let mut mismatches = parameters
+            .iter()
+            .filter(|parameter| !self.params.iter().any(|param| param.name == parameter.name))
+            .map(|parameter| ParameterMismatch::Undocumented(parameter.name.clone()))
+            .collect::<Vec<_>>();
+        mismatches.extend(
+            self.params
+                .iter()
+                .filter(|param| param.name != "self")
+                .filter(|param| !parameters.iter().any(|parameter| parameter.name == param.name))
+                .map(|param| ParameterMismatch::NotAParameter(param.name.clone())),
+        );
+        mismatches
+    }
+}
+
+// The doc comment in the leading trivia of the first token of a function or of the statement
+// or table field that assigns it.
+pub(crate) fn doc_comment(token: &TokenReference) -> Option<DocComment> {
+    // first line, last line and text of each comment, and whether it is a block comment
+    let mut comments = Vec::new();
+    for trivia in token.leading_trivia() {
+        let end_line = trivia.end_position().line();
+        match trivia.token_type() {
+            TokenType::SingleLineComment { comment } => {
+                comments.push((end_line, end_line, comment.as_ref(), false));
+            }
+            TokenType::MultiLineComment { comment, .. } => {
+                let start_line = end_line - comment.matches('\n').count();
+                comments.push((start_line, end_line, comment.as_ref(), true));
+            }
+            _ => {}
+        }
+    }
+    // comments on consecutive lines ending on the line before the token
+    let mut next_line = start_line(token);
+    let mut first = comments.len();
+    while first > 0 && comments[first - 1].1 + 1 == next_line {
+        first -= 1;
+        next_line = comments[first].0;
+    }
+    let block = &comments[first..];
+    let start = block
+        .iter()
+        .position(|(_, _, text, _)| text.starts_with('-'))?;
+    let mut lines = Vec::new();
+    for &(_, _, text, multi_line) in &block[start..] {
+        if multi_line {
+            let text = text.trim_start_matches('-');
+            lines.extend(text.lines().map(|line| (line.trim(), false)));
+        } else {
+            let text = text.trim_start_matches('-');
+            let emmy_lua = text.starts_with('@');
+            lines.push((text.strip_prefix(' ').unwrap_or(text), emmy_lua));
+        }
+    }
+    Some(DocComment::parse(lines))
+}
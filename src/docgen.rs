@@ -0,0 +1,239 @@
+// Documentation of the functions that Lua modules export, in Markdown or HTML,
+// with each function's signature, doc comment and a link to the line where it is defined.
+
+use crate::{
+    doc::{DocParam, DocReturn},
+    FunctionKind, FunctionNameLine, Module,
+};
+use std::io::{self, Write};
+
+// A parsed Lua module to document.
+#[derive(Debug, Clone, Copy)]
+pub struct DocSource<'a> {
+    // path as it should appear in the documentation, relative to where the documentation
+    // is put so that links to the source work
+    pub path: &'a str,
+    pub module: &'a Module,
+}
+
+// Functions stored in the table that a module returns, in order of definition.
+// If the module does not return a variable, its global functions.
+pub fn exported_functions(module: &Module) -> Vec<&FunctionNameLine> {
+    let returned_name = module.returned_name();
+    module
+        .function_record()
+        .iter()
+        .filter(|function| function.parent.is_none())
+        .filter(|function| match returned_name {
+            Some(name) => function.segments.len() > 1 && function.segments[0] == name,
+            None => function.kind == FunctionKind::Global,
+        })
+        .collect()
+}
+
+// Link to the line where `function` starts, in the style of GitHub and GitLab.
+fn source_link(path: &str, function: &FunctionNameLine) -> String {
+    let mut link = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            link.push(char::from(byte));
+        } else {
+            link.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    link.push_str(&format!("#L{}", function.start));
+    link
+}
+
+// ` (type, optional)` after the name of a parameter, with the type formatted by `code`.
+fn param_details(param: &DocParam, code: fn(&str) -> String) -> String {
+    let mut details = Vec::new();
+    if let Some(type_name) = &param.type_name {
+        details.push(code(type_name));
+    }
+    if param.optional {
+        details.push("optional".to_string());
+    }
+    if details.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", details.join(", "))
+    }
+}
+
+fn markdown_code(text: &str) -> String {
+    // A code span cannot contain a run of backticks as long as its delimiters.
+    let mut fence = "`".to_string();
+    while text.contains(fence.as_str()) {
+        fence.push('`');
+    }
+    let padding = if text.starts_with('`') || text.ends_with('`') { " " } else { "" };
+    format!("{0}{1}{2}{1}{0}", fence, padding, text)
+}
+
+// Doc comments may use Markdown, but HTML in them would be taken as markup,
+// so `<`, `>` and `&` are written as character references.
+fn markdown_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn write_markdown_list<W: Write>(writer: &mut W, heading: &str, items: &[String]) -> io::Result<()> {
+    if items.is_empty() {
+        return Ok(());
+    }
+    writeln!(writer, "{}:\n", heading)?;
+    for item in items {
+        // Continuation lines are indented to stay in the list item.
+        writeln!(writer, "- {}", item.replace('\n', "\n  "))?;
+    }
+    writeln!(writer)
+}
+
+fn markdown_param(param: &DocParam) -> String {
+    let mut item = markdown_code(&param.name);
+    item.push_str(&param_details(param, markdown_code));
+    if !param.description.is_empty() {
+        item.push_str(": ");
+        item.push_str(&markdown_escape(&param.description));
+    }
+    item
+}
+
+fn markdown_return(ret: &DocReturn) -> String {
+    let description = markdown_escape(&ret.description);
+    match &ret.type_name {
+        Some(type_name) if ret.description.is_empty() => markdown_code(type_name),
+        Some(type_name) => format!("{}: {}", markdown_code(type_name), description),
+        None => description,
+    }
+}
+
+pub fn write_markdown<W: Write>(mut writer: W, sources: &[DocSource]) -> io::Result<()> {
+    for source in sources {
+        writeln!(writer, "# {}\n", markdown_code(source.path))?;
+        for function in exported_functions(source.module) {
+            writeln!(writer, "## {}\n", markdown_code(&function.signature()))?;
+            if let Some(doc) = &function.doc {
+                if !doc.description.is_empty() {
+                    writeln!(writer, "{}\n", markdown_escape(&doc.description))?;
+                }
+                let params = doc.params.iter().map(markdown_param).collect::<Vec<_>>();
+                write_markdown_list(&mut writer, "Parameters", &params)?;
+                let returns = doc.returns.iter().map(markdown_return).collect::<Vec<_>>();
+                write_markdown_list(&mut writer, "Returns", &returns)?;
+            }
+            writeln!(writer, "[Source]({})\n", source_link(source.path, function))?;
+        }
+    }
+    Ok(())
+}
+
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// IDs cannot contain whitespace.
+fn html_id(text: &str) -> String {
+    html_escape(&text.replace(char::is_whitespace, "_"))
+}
+
+fn html_code(text: &str) -> String {
+    format!("<code>{}</code>", html_escape(text))
+}
+
+// Paragraphs of `text`, which are separated by blank lines.
+fn html_paragraphs(text: &str) -> String {
+    text.split("\n\n")
+        .filter(|paragraph| !paragraph.trim().is_empty())
+        .map(|paragraph| format!("<p>{}</p>\n", html_escape(paragraph.trim())))
+        .collect()
+}
+
+fn write_html_list<W: Write>(writer: &mut W, heading: &str, items: &[String]) -> io::Result<()> {
+    if items.is_empty() {
+        return Ok(());
+    }
+    writeln!(writer, "<h3>{}</h3>\n<ul>", heading)?;
+    for item in items {
+        writeln!(writer, "<li>{}</li>", item)?;
+    }
+    writeln!(writer, "</ul>")
+}
+
+fn html_param(param: &DocParam) -> String {
+    let mut item = html_code(&param.name);
+    item.push_str(&param_details(param, html_code));
+    if !param.description.is_empty() {
+        item.push_str(": ");
+        item.push_str(&html_escape(&param.description));
+    }
+    item
+}
+
+fn html_return(ret: &DocReturn) -> String {
+    let description = html_escape(&ret.description);
+    match &ret.type_name {
+        Some(type_name) if ret.description.is_empty() => html_code(type_name),
+        Some(type_name) => format!("{}: {}", html_code(type_name), description),
+        None => description,
+    }
+}
+
+// Writes a complete HTML document. Each module and function has an `id`,
+// `path` and `path:name` with whitespace replaced by `_`, to link to.
+pub fn write_html<W: Write>(mut writer: W, sources: &[DocSource]) -> io::Result<()> {
+    writeln!(
+        writer,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>API documentation</title>\n</head>\n<body>"
+    )?;
+    for source in sources {
+        writeln!(
+            writer,
+            "<section id=\"{}\">\n<h1>{}</h1>",
+            html_id(source.path),
+            html_code(source.path),
+        )?;
+        for function in exported_functions(source.module) {
+            writeln!(
+                writer,
+                "<section id=\"{}:{}\">\n<h2>{}</h2>",
+                html_id(source.path),
+                html_id(&function.name),
+                html_code(&function.signature()),
+            )?;
+            if let Some(doc) = &function.doc {
+                write!(writer, "{}", html_paragraphs(&doc.description))?;
+                let params = doc.params.iter().map(html_param).collect::<Vec<_>>();
+                write_html_list(&mut writer, "Parameters", &params)?;
+                let returns = doc.returns.iter().map(html_return).collect::<Vec<_>>();
+                write_html_list(&mut writer, "Returns", &returns)?;
+            }
+            writeln!(
+                writer,
+                "<p><a href=\"{}\">Source</a></p>\n</section>",
+                html_escape(&source_link(source.path, function)),
+            )?;
+        }
+        writeln!(writer, "</section>")?;
+    }
+    writeln!(writer, "</body>\n</html>")
+}
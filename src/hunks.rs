@@ -0,0 +1,185 @@
+// Rewrites the section headings of unified diff hunks (the text after `@@ ... @@`)
+// with the name of the function enclosing each hunk, like a `diff.<driver>.xfuncname`
+// that actually parses Lua.
+
+use crate::Module;
+use std::io::{self, Write};
+
+// The parsed versions of a file on either side of a diff. A side is `None` when it is
+// missing (`/dev/null`), not Lua, or cannot be parsed.
+#[derive(Debug, Clone, Default)]
+pub struct FilePair {
+    pub old: Option<Module>,
+    pub new: Option<Module>,
+}
+
+// Path in a `--- ` or `+++ ` line, without the timestamp some tools append after a tab.
+fn header_path(path: &str) -> Option<&str> {
+    let path = path.trim_end_matches(['\r', '\n']);
+    let path = path.split('\t').next().unwrap_or(path);
+    if path == "/dev/null" {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+struct HunkHeader<'a> {
+    // `@@ -a,b +c,d @@`, without the section heading
+    ranges: &'a str,
+    old_start: usize,
+    old_count: usize,
+    new_start: usize,
+    new_count: usize,
+}
+
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    match range.split_once(',') {
+        Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+        None => Some((range.parse().ok()?, 1)),
+    }
+}
+
+fn parse_hunk_header(line: &str) -> Option<HunkHeader<'_>> {
+    let rest = line.strip_prefix("@@ -")?;
+    let end = rest.find(" @@")?;
+    let (old, new) = rest[..end].split_once(" +")?;
+    let (old_start, old_count) = parse_range(old)?;
+    let (new_start, new_count) = parse_range(new)?;
+    Some(HunkHeader {
+        ranges: &line[.."@@ -".len() + end + " @@".len()],
+        old_start,
+        old_count,
+        new_start,
+        new_count,
+    })
+}
+
+// Name of the function enclosing the first changed line of a hunk, or an empty string
+// for the top level of the chunk. Removed lines are looked up in the old file if it
+// is available, everything else in the new file.
+fn section_name(files: &FilePair, header: &HunkHeader, hunk: &[&str]) -> Option<String> {
+    let (mut old_line, mut new_line) = (header.old_start, header.new_start);
+    let mut position = None;
+    for line in hunk {
+        match line.as_bytes().first() {
+            Some(b'+') => {
+                position = Some((files.new.as_ref(), new_line));
+                break;
+            }
+            Some(b'-') => {
+                position = Some(match files.old {
+                    Some(ref old) => (Some(old), old_line),
+                    None => (files.new.as_ref(), new_line),
+                });
+                break;
+            }
+            Some(b'\\') => {}
+            _ => {
+                old_line += 1;
+                new_line += 1;
+            }
+        }
+    }
+    let (module, line) = position.unwrap_or((files.new.as_ref(), header.new_start));
+    let module = module?;
+    Some(
+        module
+            .get_function_stack(line)
+            .last()
+            .map_or_else(String::new, |function| function.name.clone()),
+    )
+}
+
+// Index of the line after the hunk whose lines begin at `start`.
+// The line counts tell where the hunk ends, so that removed lines that
+// look like `--- ` or `+++ ` lines are not mistaken for file headers.
+fn hunk_end(lines: &[&str], start: usize, header: &HunkHeader) -> usize {
+    let (mut old_count, mut new_count) = (header.old_count, header.new_count);
+    let mut i = start;
+    while i < lines.len() && (old_count > 0 || new_count > 0) {
+        match lines[i].as_bytes().first() {
+            Some(b'+') => new_count = new_count.saturating_sub(1),
+            Some(b'-') => old_count = old_count.saturating_sub(1),
+            Some(b'\\') => {}
+            _ => {
+                old_count = old_count.saturating_sub(1);
+                new_count = new_count.saturating_sub(1);
+            }
+        }
+        i += 1;
+    }
+    // A trailing "\ No newline at end of file" still belongs to the hunk.
+    while i < lines.len() && lines[i].starts_with('\\') {
+        i += 1;
+    }
+    i
+}
+
+// Number of files in a unified diff, that is, the number of times `rewrite_hunk_headers`
+// calls `files`.
+pub fn file_count(diff: &str) -> usize {
+    let lines = diff.split_inclusive('\n').collect::<Vec<_>>();
+    let mut count = 0;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        i += 1;
+        if line.starts_with("+++ ") {
+            count += 1;
+        } else if let Some(header) = parse_hunk_header(line) {
+            i = hunk_end(&lines, i, &header);
+        }
+    }
+    count
+}
+
+// Copies a unified diff to `writer`, replacing the section heading of every hunk whose
+// file could be parsed. `files` is called with the old and new path of each file in
+// the diff, as they appear in its `---` and `+++` lines.
+pub fn rewrite_hunk_headers<W, F>(diff: &str, mut writer: W, mut files: F) -> io::Result<()>
+where
+    W: Write,
+    F: FnMut(Option<&str>, Option<&str>) -> FilePair,
+{
+    let lines = diff.split_inclusive('\n').collect::<Vec<_>>();
+    let mut current = FilePair::default();
+    let mut old_path = None;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        i += 1;
+        if let Some(path) = line.strip_prefix("--- ") {
+            old_path = header_path(path);
+        } else if let Some(path) = line.strip_prefix("+++ ") {
+            current = files(old_path.take(), header_path(path));
+        } else if let Some(header) = parse_hunk_header(line) {
+            let start = i;
+            i = hunk_end(&lines, start, &header);
+            let hunk = &lines[start..i];
+            match section_name(&current, &header, hunk) {
+                Some(name) => {
+                    let newline = if line.ends_with("\r\n") {
+                        "\r\n"
+                    } else if line.ends_with('\n') {
+                        "\n"
+                    } else {
+                        ""
+                    };
+                    if name.is_empty() {
+                        write!(writer, "{}{}", header.ranges, newline)?;
+                    } else {
+                        write!(writer, "{} {}{}", header.ranges, name, newline)?;
+                    }
+                }
+                None => writer.write_all(line.as_bytes())?,
+            }
+            for line in hunk {
+                writer.write_all(line.as_bytes())?;
+            }
+            continue;
+        }
+        writer.write_all(line.as_bytes())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use crate::{FunctionKind, FunctionNameLine, Module};
+use serde::Serialize;
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+// One function as it appears in JSON output.
+// `index` and `parent` are indices into the functions of the same file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FunctionRecord<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<&'a str>,
+    pub index: usize,
+    pub name: &'a str,
+    pub segments: &'a [String],
+    pub signature: String,
+    pub kind: FunctionKind,
+    pub start: Position,
+    pub end: Position,
+    pub parent: Option<usize>,
+}
+
+impl<'a> FunctionRecord<'a> {
+    pub fn new(file: Option<&'a str>, index: usize, function: &'a FunctionNameLine) -> Self {
+        FunctionRecord {
+            file,
+            index,
+            name: &function.name,
+            segments: &function.segments,
+            signature: function.signature(),
+            kind: function.kind,
+            start: Position {
+                line: function.start,
+                column: function.start_column,
+            },
+            end: Position {
+                line: function.end,
+                column: function.end_column,
+            },
+            parent: function.parent,
+        }
+    }
+}
+
+pub fn records<'a>(
+    file: Option<&'a str>,
+    module: &'a Module,
+) -> impl Iterator<Item = FunctionRecord<'a>> {
+    module
+        .function_record()
+        .iter()
+        .enumerate()
+        .map(move |(i, function)| FunctionRecord::new(file, i, function))
+}
+
+// Writes the functions of all modules as a single JSON array.
+pub fn write_document<'a, W: Write>(
+    writer: W,
+    modules: impl IntoIterator<Item = (Option<&'a str>, &'a Module)>,
+) -> serde_json::Result<()> {
+    let records = modules
+        .into_iter()
+        .flat_map(|(file, module)| records(file, module))
+        .collect::<Vec<_>>();
+    serde_json::to_writer(writer, &records)
+}
+
+// Writes the functions of one module as newline-delimited JSON, one object per line,
+// so that the output for many files can be streamed.
+pub fn write_lines<W: Write>(
+    mut writer: W,
+    file: Option<&str>,
+    module: &Module,
+) -> serde_json::Result<()> {
+    for record in records(file, module) {
+        serde_json::to_writer(&mut writer, &record)?;
+        writer.write_all(b"\n").map_err(serde_json::Error::io)?;
+    }
+    Ok(())
+}
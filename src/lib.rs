@@ -1,16 +1,14 @@
 use full_moon::{
-    ast::BinOp,
     ast::Field,
     ast::FunctionCall,
     ast::FunctionName,
     ast::TableConstructor,
-    ast::UnOp,
     ast::VarExpression,
     ast::{
-        AstError, Block, Call, Expression, FunctionArgs, Index, Prefix, Stmt, Suffix, Value, Var,
+        Ast, AstError, Block, Call, Expression, FunctionArgs, FunctionBody, Index, LastStmt, Prefix, Stmt,
+        Suffix, Value, Var,
     },
-    tokenizer::Token,
-    tokenizer::{TokenReference, TokenType},
+    tokenizer::{Symbol, Token, TokenReference, TokenType},
 };
 use itertools::{EitherOrBoth, Itertools};
 use std::{borrow::Cow, convert::TryFrom, convert::TryInto, fmt::Display};
@@ -18,6 +16,23 @@ use std::{borrow::Cow, convert::TryFrom, convert::TryInto, fmt::Display};
 mod traits;
 use traits::FirstToken;
 
+use doc::{doc_comment, DocComment};
+
+#[cfg(feature = "luau")]
+mod luau;
+
+pub mod bytecode;
+pub mod coverage;
+pub mod diff;
+pub mod doc;
+pub mod docgen;
+pub mod hunks;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod tags;
+
 fn unexpected_token<'a>(token_ref: &'a TokenReference<'a>, msg: &'_ str) -> AstError<'a> {
     AstError::UnexpectedToken {
         token: token_ref.token().to_owned(),
@@ -25,17 +40,246 @@ fn unexpected_token<'a>(token_ref: &'a TokenReference<'a>, msg: &'_ str) -> AstE
     }
 }
 
-#[derive(Debug, PartialEq)]
+// full_moon reports the start of a token at the beginning of a line
+// as the end of the previous line, but the end position is accurate.
+// Only strings and comments can span lines.
+fn start_line(token: &Token) -> usize {
+    token.end_position().line() - token.to_string().matches('\n').count()
+}
+
+fn start_column(token: &Token) -> usize {
+    let text = token.to_string();
+    if text.contains('\n') {
+        let start = token.start_position();
+        if start.line() == start_line(token) {
+            start.character()
+        } else {
+            1
+        }
+    } else {
+        token.end_position().character().saturating_sub(text.chars().count())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    // invalid token, such as an unclosed string
+    Tokenize,
+    // syntax error
+    Parse,
+    // syntax that the function finder does not understand
+    Analysis,
+    // code nested more deeply than `Options::max_depth`
+    Nesting,
+    // a dialect that this build cannot parse (see `Dialect`)
+    Unsupported,
+    // the system could not provide what parsing needs, such as a thread with a large stack
+    Resource,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub message: String,
+    // line and column (both 1-based) where the error was found, if known
+    pub position: Option<(usize, usize)>,
+}
+
+impl Error {
+    fn from_ast_error(kind: ErrorKind, error: AstError) -> Self {
+        match error {
+            AstError::UnexpectedToken { token, additional } => {
+                let mut message = format!("unexpected token `{}`", token);
+                if let Some(additional) = additional {
+                    message.push_str(": ");
+                    message.push_str(&additional);
+                }
+                let position = (start_line(&token), start_column(&token));
+                Error {
+                    kind,
+                    message,
+                    position: Some(position),
+                }
+            }
+            error => Error {
+                kind,
+                message: error.to_string(),
+                position: None,
+            },
+        }
+    }
+}
+
+impl<'a> From<full_moon::Error<'a>> for Error {
+    fn from(error: full_moon::Error<'a>) -> Self {
+        match error {
+            full_moon::Error::AstError(error) => Error::from_ast_error(ErrorKind::Parse, error),
+            full_moon::Error::TokenizerError(error) => {
+                // The position is only available through the message,
+                // which ends with `at line {line}, column {column}`.
+                let message = error.to_string();
+                let (message, position) = match message.rsplit_once(" at line ") {
+                    Some((description, position)) => {
+                        let position = position.split_once(", column ").and_then(|(line, column)| {
+                            Some((line.parse().ok()?, column.parse().ok()?))
+                        });
+                        (description.to_string(), position)
+                    }
+                    None => (message, None),
+                };
+                Error {
+                    kind: ErrorKind::Tokenize,
+                    message,
+                    position,
+                }
+            }
+        }
+    }
+}
+
+impl<'a> From<AstError<'a>> for Error {
+    fn from(error: AstError<'a>) -> Self {
+        Error::from_ast_error(ErrorKind::Analysis, error)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some((line, column)) = self.position {
+            write!(f, "{}:{}: ", line, column)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct FunctionSpan<'a> {
     pub start: usize,
     pub end: usize,
+    // column of the first character of the function and one past the last character of `end`
+    pub start_column: usize,
+    pub end_column: usize,
+    // line and column of the first character of the name and one past its last character,
+    // or of the `function` keyword if the function has no name
+    pub name_start: (usize, usize),
+    pub name_end: (usize, usize),
     pub name: FunctionNameStack<'a>,
+    // lines on which a statement of the function body (but not of a nested function) begins
+    pub executable_lines: Vec<usize>,
+    // hash of the tokens of the parameters and body, ignoring whitespace and comments,
+    // which stays the same when the function is moved, renamed or reformatted
+    pub fingerprint: u64,
+    pub parameters: Vec<Parameter>,
+    // Luau return type annotation
+    pub return_type: Option<String>,
+    pub doc: Option<DocComment>,
+}
+
+// A parameter of a function, `...` for varargs.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Parameter {
+    pub name: String,
+    // Luau type annotation, without the `:`
+    pub type_annotation: Option<String>,
+}
+
+// Text of `node` with comments removed and each run of whitespace replaced with a space.
+#[cfg(feature = "luau")]
+fn compact_text<'a>(node: &impl full_moon::node::Node<'a>) -> String {
+    // full_moon gives the tokens of brackets before the tokens between them.
+    let mut tokens = node.tokens().collect::<Vec<_>>();
+    tokens.sort_by_key(|token| token.end_position().bytes());
+    let mut text = String::new();
+    let mut space = false;
+    for token in tokens {
+        space |= token.leading_trivia().next().is_some();
+        if space && !text.is_empty() {
+            text.push(' ');
+        }
+        text.push_str(&token.token().to_string());
+        space = token.trailing_trivia().next().is_some();
+    }
+    text
+}
+
+fn parameters(body: &FunctionBody) -> Vec<Parameter> {
+    let names = body.iter_parameters().map(|parameter| match parameter {
+        full_moon::ast::Parameter::Ellipse(token) | full_moon::ast::Parameter::Name(token) => {
+            token.token().to_string()
+        }
+    });
+    #[cfg(feature = "luau")]
+    let types = body
+        .type_specifiers()
+        .map(|specifier| specifier.map(|specifier| compact_text(specifier.type_info())))
+        .chain(std::iter::repeat(None));
+    #[cfg(not(feature = "luau"))]
+    let types = std::iter::repeat(None);
+    names
+        .zip(types)
+        .map(|(name, type_annotation)| Parameter { name, type_annotation })
+        .collect()
+}
+
+#[cfg(feature = "luau")]
+fn return_type(body: &FunctionBody) -> Option<String> {
+    body.return_type().map(|specifier| compact_text(specifier.type_info()))
+}
+
+#[cfg(not(feature = "luau"))]
+fn return_type(_: &FunctionBody) -> Option<String> {
+    None
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+// FNV-1a, which unlike `DefaultHasher` gives the same result in every build.
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn fingerprint(body: &FunctionBody) -> u64 {
+    full_moon::node::Node::tokens(body).fold(FNV_OFFSET_BASIS, |hash, token| {
+        // Tokens are separated by a byte that cannot occur in a token.
+        fnv1a(fnv1a(hash, token.token().to_string().as_bytes()), &[0])
+    })
+}
+
+impl<'a> FunctionSpan<'a> {
+    fn new(
+        first_token: &'a TokenReference<'a>,
+        body: &'a FunctionBody<'a>,
+        name: FunctionNameStack<'a>,
+    ) -> Self {
+        let end = body.end_token().end_position();
+        let doc = doc_comment(name.documented_by.unwrap_or(first_token));
+        let (name_first, name_last) = name.name_tokens.unwrap_or((first_token, first_token));
+        FunctionSpan {
+            start: start_line(first_token),
+            end: end.line(),
+            start_column: start_column(first_token),
+            end_column: end.character(),
+            name_start: (start_line(name_first), start_column(name_first)),
+            name_end: (name_last.end_position().line(), name_last.end_position().character()),
+            name,
+            executable_lines: executable_lines(body.block()),
+            fingerprint: fingerprint(body),
+            parameters: parameters(body),
+            return_type: return_type(body),
+            doc,
+        }
+    }
 }
 
 fn remove_trivia<'a>(token_ref: &'a TokenReference<'a>) -> TokenReference<'a> {
     TokenReference::new(vec![], token_ref.token().to_owned(), vec![])
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone, Debug, PartialEq)]
 enum FunctionNameSegment<'a> {
     Anonymous,
@@ -75,6 +319,8 @@ impl<'a> TryFrom<TableKey<'a>> for FunctionNameSegment<'a> {
                         vec![],
                     )))),
                     binop: None,
+                    #[cfg(feature = "luau")]
+                    as_assertion: None,
                 }))
             }
             TableKey::Name(token) => token.as_ref().try_into()?,
@@ -92,6 +338,14 @@ pub struct FunctionNameStack<'a> {
     middle: Vec<FunctionNameSegment<'a>>,
     // optional method name
     method: Option<FunctionNameSegment<'a>>,
+    // whether the identifier is declared with `local` where the function is defined
+    local: bool,
+    // first token of the statement or table field that assigns the function,
+    // whose leading comments document it
+    documented_by: Option<&'a TokenReference<'a>>,
+    // first and last tokens of the name, or of the key of the table field, that the function is
+    // assigned to
+    name_tokens: Option<(&'a TokenReference<'a>, &'a TokenReference<'a>)>,
 }
 
 impl<'a> FunctionNameStack<'a> {
@@ -100,12 +354,53 @@ impl<'a> FunctionNameStack<'a> {
             first,
             middle: vec![],
             method: None,
+            local: false,
+            documented_by: None,
+            name_tokens: None,
         }
     }
 
     fn anonymous() -> Self {
         Self::new(FunctionNameSegment::Anonymous)
     }
+
+    pub fn kind(&self) -> FunctionKind {
+        if self.method.is_some() {
+            FunctionKind::Method
+        } else if !self.middle.is_empty() {
+            FunctionKind::Field
+        } else if self.first == FunctionNameSegment::Anonymous {
+            FunctionKind::Anonymous
+        } else if self.local {
+            FunctionKind::Local
+        } else {
+            FunctionKind::Global
+        }
+    }
+
+    // Each part of the name: the identifier, fields (`name` or `[expression]`) and method name.
+    pub fn segments(&self) -> Vec<String> {
+        std::iter::once(&self.first)
+            .chain(self.middle.iter())
+            .chain(self.method.iter())
+            .map(ToString::to_string)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd, Hash)]
+#[cfg_attr(feature = "json", derive(serde::Serialize), serde(rename_all = "lowercase"))]
+pub enum FunctionKind {
+    // `local function f() end` or `local f = function() end`
+    Local,
+    // `function f() end` or `f = function() end`
+    Global,
+    // `function t.f() end`, `t.f = function() end` or `{ f = function() end }`
+    Field,
+    // `function t:f() end`
+    Method,
+    // function without a name, such as a function argument
+    Anonymous,
 }
 
 impl<'a> FunctionNameStack<'a> {
@@ -119,54 +414,43 @@ impl<'a> FunctionNameStack<'a> {
     }
 }
 
-impl<'a> Display for FunctionNameStack<'a> {
+impl<'a> Display for FunctionNameSegment<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match &self.first {
-            FunctionNameSegment::Anonymous => write!(f, "?")?,
-            FunctionNameSegment::Name(name) => write!(f, "{}", name)?,
+        match self {
+            FunctionNameSegment::Anonymous => write!(f, "?"),
+            FunctionNameSegment::Name(name) => write!(f, "{}", name),
             FunctionNameSegment::Expression(expr) => {
-                if let Expression::Value { value, binop: None } = expr.as_ref() {
+                if let Expression::Value { value, binop: None, .. } = expr.as_ref() {
                     if let Value::String(token) | Value::Number(token) | Value::Symbol(token) =
                         value.as_ref()
                     {
-                        write!(f, "[{}]", remove_trivia(token))?;
+                        write!(f, "[{}]", remove_trivia(token))
                     } else {
-                        write!(f, "[{}]", value)?;
+                        write!(f, "[{}]", value)
                     }
                 } else {
-                    write!(f, "[{}]", expr)?;
+                    write!(f, "[{}]", expr)
                 }
             }
         }
+    }
+}
+
+impl<'a> Display for FunctionNameStack<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.first)?;
         for segment in self.middle.iter() {
-            match &segment {
-                FunctionNameSegment::Anonymous => {
-                    // This should not happen.
-                    write!(f, ".?")?
-                }
-                FunctionNameSegment::Name(name) => {
-                    write!(f, ".{}", name)?;
-                }
-                FunctionNameSegment::Expression(expr) => {
-                    if let Expression::Value { value, binop: None } = expr.as_ref() {
-                        if let Value::String(token) | Value::Number(token) | Value::Symbol(token) =
-                            value.as_ref()
-                        {
-                            write!(f, "[{}]", remove_trivia(token))?;
-                        } else {
-                            write!(f, "[{}]", value)?;
-                        }
-                    } else {
-                        write!(f, "[{}]", expr)?;
-                    }
+            match segment {
+                // Anonymous segments should not happen.
+                FunctionNameSegment::Anonymous | FunctionNameSegment::Name(_) => {
+                    write!(f, ".{}", segment)?
                 }
+                FunctionNameSegment::Expression(_) => write!(f, "{}", segment)?,
             }
         }
-        if let Some(method) = &self.method {
-            // This should be infallible.
-            if let FunctionNameSegment::Name(name) = method {
-                write!(f, ":{}", name)?;
-            }
+        // This should be infallible.
+        if let Some(method @ FunctionNameSegment::Name(_)) = &self.method {
+            write!(f, ":{}", method)?;
         }
         Ok(())
     }
@@ -192,10 +476,18 @@ impl<'a> TryFrom<&'a FunctionName<'a>> for FunctionNameStack<'a> {
             .map(|id| id.as_ref().try_into())
             .collect::<Result<_, _>>()?;
         let method = name.method_name().map(TryInto::try_into).transpose()?;
+        let first_token = name.names().iter().next().expect("checked above").as_ref();
+        let last_token = name
+            .method_name()
+            .or_else(|| name.names().iter().last().map(AsRef::as_ref))
+            .expect("checked above");
         Ok(Self {
             first,
             middle,
             method,
+            local: false,
+            documented_by: None,
+            name_tokens: Some((first_token, last_token)),
         })
     }
 }
@@ -204,18 +496,19 @@ impl<'a> TryFrom<&'a VarExpression<'a>> for FunctionNameStack<'a> {
     type Error = AstError<'a>;
 
     fn try_from(var_expr: &'a VarExpression<'a>) -> Result<Self, Self::Error> {
-        let mut stack = match var_expr.prefix() {
+        let (mut stack, first_token) = match var_expr.prefix() {
             Prefix::Expression(expr) => {
                 return Err(unexpected_token(expr.first_token(), "expected identifier"))
             }
             Prefix::Name(name) => {
                 if let TokenType::Identifier { identifier } = name.token_type() {
-                    FunctionNameStack::new(FunctionNameSegment::Name(identifier))
+                    (FunctionNameStack::new(FunctionNameSegment::Name(identifier)), name.as_ref())
                 } else {
                     return Err(unexpected_token(name, "expected identifier"));
                 }
             }
         };
+        let mut last_token = first_token;
         for suffix in var_expr.iter_suffixes() {
             let segment = match suffix {
                 Suffix::Call(call) => {
@@ -225,10 +518,12 @@ impl<'a> TryFrom<&'a VarExpression<'a>> for FunctionNameStack<'a> {
                     ))
                 }
                 Suffix::Index(index) => match index {
-                    Index::Brackets { expression, .. } => {
+                    Index::Brackets { brackets, expression } => {
+                        last_token = brackets.tokens().1;
                         FunctionNameSegment::Expression(Cow::Borrowed(expression))
                     }
                     Index::Dot { name, .. } => {
+                        last_token = name.as_ref();
                         if let TokenType::Identifier { identifier } = name.token_type() {
                             FunctionNameSegment::Name(identifier)
                         } else {
@@ -239,6 +534,7 @@ impl<'a> TryFrom<&'a VarExpression<'a>> for FunctionNameStack<'a> {
             };
             stack.push(segment);
         }
+        stack.name_tokens = Some((first_token, last_token));
         Ok(stack)
     }
 }
@@ -248,9 +544,9 @@ impl<'a> TryFrom<&'a TokenReference<'a>> for FunctionNameStack<'a> {
 
     fn try_from(token_ref: &'a TokenReference<'a>) -> Result<Self, Self::Error> {
         if let TokenType::Identifier { identifier } = token_ref.token_type() {
-            Ok(FunctionNameStack::new(FunctionNameSegment::Name(
-                identifier,
-            )))
+            let mut stack = FunctionNameStack::new(FunctionNameSegment::Name(identifier));
+            stack.name_tokens = Some((token_ref, token_ref));
+            Ok(stack)
         } else {
             Err(unexpected_token(token_ref, "expected identifier"))
         }
@@ -267,9 +563,34 @@ impl<'a> TryFrom<&'a Var<'a>> for FunctionNameStack<'a> {
     }
 }
 
-fn process_suffixes<'a, 'b>(
+// Syntax that remains to be searched for functions by `visit_functions`,
+// which keeps a stack of these instead of recursing, so that deeply nested code
+// does not overflow the call stack.
+#[allow(clippy::large_enum_variant)]
+enum Work<'a> {
+    Block(&'a Block<'a>),
+    Statement(&'a Stmt<'a>),
+    // an expression and the name of what it is assigned to
+    Expression(FunctionNameStack<'a>, &'a Expression<'a>),
+    // a function to enter, and then to search the body of
+    Function(FunctionSpan<'a>, &'a TokenReference<'a>, &'a FunctionBody<'a>),
+    // a function whose body has been searched
+    ExitFunction(FunctionSpan<'a>, FunctionNode<'a>),
+}
+
+impl<'a> Work<'a> {
+    fn function(
+        first_token: &'a TokenReference<'a>,
+        body: &'a FunctionBody<'a>,
+        name: FunctionNameStack<'a>,
+    ) -> Self {
+        Work::Function(FunctionSpan::new(first_token, body, name), first_token, body)
+    }
+}
+
+fn process_suffixes<'a>(
     suffixes: impl Iterator<Item = &'a Suffix<'a>> + 'a,
-    functions: &'b mut Vec<FunctionSpan<'a>>,
+    work: &mut Vec<Work<'a>>,
 ) -> Result<(), AstError<'a>> {
     for suffix in suffixes {
         if let Suffix::Call(call) = suffix {
@@ -280,15 +601,11 @@ fn process_suffixes<'a, 'b>(
             match args {
                 FunctionArgs::Parentheses { arguments, .. } => {
                     for arg in arguments {
-                        process_expression(&mut FunctionNameStack::anonymous(), arg, functions)?;
+                        work.push(Work::Expression(FunctionNameStack::anonymous(), arg));
                     }
                 }
                 FunctionArgs::TableConstructor(table) => {
-                    process_table_constructor(
-                        &mut FunctionNameStack::anonymous(),
-                        table,
-                        functions,
-                    )?;
+                    process_table_constructor(&FunctionNameStack::anonymous(), table, work)?;
                 }
                 FunctionArgs::String(_) => {}
             }
@@ -297,14 +614,14 @@ fn process_suffixes<'a, 'b>(
     Ok(())
 }
 
-fn process_function_call<'a, 'b>(
+fn process_function_call<'a>(
     call: &'a FunctionCall<'a>,
-    functions: &'b mut Vec<FunctionSpan<'a>>,
+    work: &mut Vec<Work<'a>>,
 ) -> Result<(), AstError<'a>> {
     if let Prefix::Expression(expr) = call.prefix() {
-        process_expression(&mut FunctionNameStack::anonymous(), expr, functions)?;
+        work.push(Work::Expression(FunctionNameStack::anonymous(), expr));
     }
-    process_suffixes(call.iter_suffixes(), functions)?;
+    process_suffixes(call.iter_suffixes(), work)?;
     Ok(())
 }
 
@@ -329,80 +646,75 @@ impl<'a> TableKey<'a> {
 }
 
 fn process_table_constructor<'a>(
-    name: &mut FunctionNameStack<'a>,
+    name: &FunctionNameStack<'a>,
     table: &'a TableConstructor<'a>,
-    functions: &mut Vec<FunctionSpan<'a>>,
+    work: &mut Vec<Work<'a>>,
 ) -> Result<(), AstError<'a>> {
     let mut index = 0;
-    for (key, value) in table
-        .iter_fields()
-        .map(|(field, _)| TableKey::with_value_from_field(field, &mut index))
-    {
+    for field in table.iter_fields().map(|(field, _)| field) {
+        let (key, value) = TableKey::with_value_from_field(field, &mut index);
         if let TableKey::Expression(expr) = key {
-            process_expression(&mut FunctionNameStack::anonymous(), expr, functions)?;
+            work.push(Work::Expression(FunctionNameStack::anonymous(), expr));
         }
-        name.push(key.clone().try_into()?);
-        process_expression(name, value, functions)?;
-        name.pop();
+        let mut name = name.clone();
+        name.name_tokens = match field {
+            Field::ExpressionKey { brackets, .. } => Some(brackets.tokens()),
+            Field::NameKey { key, .. } => Some((key.as_ref(), key.as_ref())),
+            Field::NoKey(_) => None,
+        };
+        name.push(key.try_into()?);
+        name.documented_by = Some(field.first_token());
+        work.push(Work::Expression(name, value));
     }
     Ok(())
 }
 
 enum UsefulExpression<'a> {
-    Single(&'a Box<Value<'a>>),
-    UnOp(&'a UnOp<'a>, &'a Expression<'a>),
-    BinOp(&'a Box<Value<'a>>, &'a BinOp<'a>, &'a Expression<'a>),
+    Single(&'a Value<'a>),
+    UnOp(&'a Expression<'a>),
+    BinOp(&'a Value<'a>, &'a Expression<'a>),
 }
 
 fn strip_parentheses<'a>(mut expr: &'a Expression<'a>) -> UsefulExpression<'a> {
-    while let Expression::Parentheses { expression, .. } = expr {
-        expr = expression.as_ref();
-    }
-    match expr {
-        Expression::Parentheses { .. } => unreachable!("parentheses have been stripped"),
-        Expression::UnaryOperator { unop, expression } => UsefulExpression::UnOp(unop, expression),
-        Expression::Value { value, binop } => match binop {
-            Some(op) => UsefulExpression::BinOp(value, op.bin_op(), op.rhs()),
-            None => {
-                if let Value::ParseExpression(expr) = value.as_ref() {
-                    strip_parentheses(expr)
-                } else {
-                    UsefulExpression::Single(value)
+    loop {
+        match expr {
+            Expression::Parentheses { expression, .. } => expr = expression,
+            Expression::UnaryOperator { expression, .. } => return UsefulExpression::UnOp(expression),
+            Expression::Value { value, binop, .. } => match binop {
+                Some(op) => return UsefulExpression::BinOp(value, op.rhs()),
+                None => {
+                    if let Value::ParseExpression(inner) = value.as_ref() {
+                        expr = inner;
+                    } else {
+                        return UsefulExpression::Single(value);
+                    }
                 }
-            }
-        },
+            },
+        }
     }
 }
 
-fn process_value<'a, 'b>(
-    var: &mut FunctionNameStack<'a>,
+fn process_value<'a>(
+    var: FunctionNameStack<'a>,
     value: &'a Value<'a>,
-    functions: &'b mut Vec<FunctionSpan<'a>>,
+    work: &mut Vec<Work<'a>>,
 ) -> Result<(), AstError<'a>> {
-    // println!("{} = {}; {:?}", var, value, functions);
     match value {
         Value::Function((keyword, body)) => {
-            let start = keyword.start_position().line();
-            let end = body.end_token().end_position().line();
-            functions.push(FunctionSpan {
-                start,
-                end,
-                name: var.clone(),
-            });
-            gather_function_line_spans(body.block(), functions)?;
+            work.push(Work::function(keyword, body, var));
         }
         Value::ParseExpression(expr) => {
-            process_expression(var, expr, functions)?;
+            work.push(Work::Expression(var, expr));
         }
         Value::FunctionCall(call) => {
-            process_function_call(call, functions)?;
+            process_function_call(call, work)?;
         }
         Value::TableConstructor(table) => {
-            process_table_constructor(var, table, functions)?;
+            process_table_constructor(&var, table, work)?;
         }
         Value::Var(var) => {
             if let Var::Expression(expr) = var {
-                process_suffixes(expr.iter_suffixes(), functions)?;
+                process_suffixes(expr.iter_suffixes(), work)?;
             }
         }
         Value::Number(_) | Value::String(_) | Value::Symbol(_) => {}
@@ -410,23 +722,21 @@ fn process_value<'a, 'b>(
     Ok(())
 }
 
-fn process_expression<'a, 'b>(
-    var: &mut FunctionNameStack<'a>,
+fn process_expression<'a>(
+    var: FunctionNameStack<'a>,
     expr: &'a Expression<'a>,
-    functions: &'b mut Vec<FunctionSpan<'a>>,
+    work: &mut Vec<Work<'a>>,
 ) -> Result<(), AstError<'a>> {
-    // println!("{} = {}", var, expr);
-    let expr = strip_parentheses(expr);
-    match expr {
+    match strip_parentheses(expr) {
         UsefulExpression::Single(value) => {
-            process_value(var, value, functions)?;
+            process_value(var, value, work)?;
         }
-        UsefulExpression::UnOp(_, value) => {
-            process_expression(&mut FunctionNameStack::anonymous(), value, functions)?;
+        UsefulExpression::UnOp(value) => {
+            work.push(Work::Expression(FunctionNameStack::anonymous(), value));
         }
-        UsefulExpression::BinOp(left, _, right) => {
-            process_value(&mut FunctionNameStack::anonymous(), left, functions)?;
-            process_expression(&mut FunctionNameStack::anonymous(), right, functions)?;
+        UsefulExpression::BinOp(left, right) => {
+            process_value(FunctionNameStack::anonymous(), left, work)?;
+            work.push(Work::Expression(FunctionNameStack::anonymous(), right));
         }
     }
     Ok(())
@@ -434,138 +744,817 @@ fn process_expression<'a, 'b>(
 
 fn process_assignment<
     'a,
-    'b,
     N: Iterator<Item = T>,
     E: Iterator<Item = &'a Expression<'a>>,
     T: TryInto<FunctionNameStack<'a>, Error = AstError<'a>> + 'a,
 >(
     name_list: N,
     expr_list: E,
-    functions: &'b mut Vec<FunctionSpan<'a>>,
+    local: bool,
+    statement: &'a Stmt<'a>,
+    work: &mut Vec<Work<'a>>,
 ) -> Result<(), AstError<'a>> {
-    for item in name_list.into_iter().zip_longest(expr_list.into_iter()) {
-        let (mut name, expr) = match item {
-            EitherOrBoth::Both(var, expr) => (var.try_into()?, expr),
+    for (i, item) in name_list.zip_longest(expr_list).enumerate() {
+        let (name, expr) = match item {
+            EitherOrBoth::Both(var, expr) => {
+                let mut name: FunctionNameStack = var.try_into()?;
+                name.local = local;
+                // A doc comment before the statement is for the first function assigned in it.
+                if i == 0 {
+                    name.documented_by = Some(statement.first_token());
+                }
+                (name, expr)
+            }
             EitherOrBoth::Right(expr) => (FunctionNameStack::anonymous(), expr),
             EitherOrBoth::Left(_) => continue,
         };
-        process_expression(&mut name, expr, functions)?;
+        work.push(Work::Expression(name, expr));
     }
     Ok(())
 }
 
-pub fn gather_function_line_spans<'a, 'b>(
-    block: &'a Block<'a>,
-    functions: &'b mut Vec<FunctionSpan<'a>>,
+fn process_statement<'a>(
+    statement: &'a Stmt<'a>,
+    work: &mut Vec<Work<'a>>,
 ) -> Result<(), AstError<'a>> {
-    for statement in block.iter_stmts() {
-        match statement {
-            Stmt::LocalFunction(func) => {
-                let start = func.local_token().start_position().line();
-                let end = func.func_body().end_token().end_position().line();
-                let name = func.name().try_into()?;
-                functions.push(FunctionSpan { name, start, end });
-                gather_function_line_spans(func.func_body().block(), functions)?;
-            }
-            Stmt::FunctionDeclaration(func) => {
-                let start = func.function_token().start_position().line();
-                let end = func.body().end_token().end_position().line();
-                functions.push(FunctionSpan {
-                    name: func.name().try_into()?,
-                    start,
-                    end,
-                });
-                gather_function_line_spans(func.body().block(), functions)?;
-            }
-            Stmt::Assignment(asgn) => {
-                process_assignment(asgn.var_list().iter(), asgn.expr_list().iter(), functions)?;
+    match statement {
+        Stmt::LocalFunction(func) => {
+            let mut name: FunctionNameStack = func.name().try_into()?;
+            name.local = true;
+            work.push(Work::function(func.local_token(), func.func_body(), name));
+        }
+        Stmt::FunctionDeclaration(func) => {
+            let name = func.name().try_into()?;
+            work.push(Work::function(func.function_token(), func.body(), name));
+        }
+        Stmt::Assignment(asgn) => {
+            process_assignment(
+                asgn.var_list().iter(),
+                asgn.expr_list().iter(),
+                false,
+                statement,
+                work,
+            )?;
+        }
+        Stmt::LocalAssignment(asgn) => {
+            process_assignment(
+                asgn.name_list().iter().map(|name| name.as_ref()),
+                asgn.expr_list().iter(),
+                true,
+                statement,
+                work,
+            )?;
+        }
+        Stmt::FunctionCall(call) => {
+            process_function_call(call, work)?;
+        }
+        Stmt::GenericFor(for_stmt) => {
+            work.push(Work::Block(for_stmt.block()));
+        }
+        Stmt::Do(do_stmt) => {
+            work.push(Work::Block(do_stmt.block()));
+        }
+        Stmt::NumericFor(for_stmt) => {
+            work.push(Work::Block(for_stmt.block()));
+        }
+        Stmt::Repeat(repeat_stmt) => {
+            work.push(Work::Block(repeat_stmt.block()));
+        }
+        Stmt::While(while_stmt) => {
+            work.push(Work::Block(while_stmt.block()));
+        }
+        Stmt::If(if_stmt) => {
+            work.push(Work::Block(if_stmt.block()));
+            if let Some(blocks) = if_stmt.else_if() {
+                for block in blocks {
+                    work.push(Work::Block(block.block()));
+                }
             }
-            Stmt::LocalAssignment(asgn) => {
-                process_assignment(
-                    asgn.name_list().iter().map(|name| name.as_ref()),
-                    asgn.expr_list().iter(),
-                    functions,
-                )?;
+            if let Some(block) = if_stmt.else_block() {
+                work.push(Work::Block(block));
             }
-            Stmt::FunctionCall(call) => {
-                process_function_call(call, functions)?;
+        }
+        #[cfg(feature = "luau")]
+        Stmt::CompoundAssignment(asgn) => {
+            if let Var::Expression(expr) = asgn.lhs() {
+                process_suffixes(expr.iter_suffixes(), work)?;
             }
-            Stmt::GenericFor(for_stmt) => {
-                gather_function_line_spans(for_stmt.block(), functions)?;
+            work.push(Work::Expression(FunctionNameStack::anonymous(), asgn.rhs()));
+        }
+        #[cfg(feature = "luau")]
+        Stmt::Continue(_) | Stmt::TypeDeclaration(_) => {}
+    }
+    Ok(())
+}
+
+// The syntax of a function found in a block.
+#[derive(Debug, Clone, Copy)]
+pub struct FunctionNode<'a> {
+    // `local` for a local function declaration, otherwise `function`
+    pub first_token: &'a TokenReference<'a>,
+    pub body: &'a FunctionBody<'a>,
+    // the statement that contains the function, such as the `local function` statement
+    // or the assignment or call that the function is an expression in
+    pub statement: &'a Stmt<'a>,
+}
+
+// Receives the functions that `visit_functions` finds, so that other information about them
+// can be collected in the same pass. Functions are entered in the order in which they start.
+// A function is exited after the functions nested in it have been entered and exited.
+pub trait FunctionVisitor<'a> {
+    fn enter_function(&mut self, _span: &FunctionSpan<'a>, _node: &FunctionNode<'a>) {}
+    fn exit_function(&mut self, _span: &FunctionSpan<'a>, _node: &FunctionNode<'a>) {}
+}
+
+impl<'a> FunctionVisitor<'a> for Vec<FunctionSpan<'a>> {
+    fn enter_function(&mut self, span: &FunctionSpan<'a>, _: &FunctionNode<'a>) {
+        self.push(span.clone());
+    }
+}
+
+pub fn visit_functions<'a>(
+    block: &'a Block<'a>,
+    visitor: &mut impl FunctionVisitor<'a>,
+) -> Result<(), AstError<'a>> {
+    // each item with the statement that it is part of
+    let mut stack = vec![(None, Work::Block(block))];
+    // work found while processing one item, in source order
+    let mut found = Vec::new();
+    while let Some((mut statement, item)) = stack.pop() {
+        match item {
+            Work::Block(block) => found.extend(block.iter_stmts().map(Work::Statement)),
+            Work::Statement(stmt) => {
+                statement = Some(stmt);
+                process_statement(stmt, &mut found)?;
             }
-            Stmt::Do(do_stmt) => {
-                gather_function_line_spans(do_stmt.block(), functions)?;
+            Work::Expression(name, expr) => process_expression(name, expr, &mut found)?,
+            Work::Function(span, first_token, body) => {
+                let node = FunctionNode {
+                    first_token,
+                    body,
+                    statement: statement.expect("functions are found in statements"),
+                };
+                visitor.enter_function(&span, &node);
+                found.push(Work::Block(body.block()));
+                found.push(Work::ExitFunction(span, node));
             }
-            Stmt::NumericFor(for_stmt) => {
-                gather_function_line_spans(for_stmt.block(), functions)?;
+            Work::ExitFunction(span, node) => visitor.exit_function(&span, &node),
+        }
+        stack.extend(found.drain(..).rev().map(|item| (statement, item)));
+    }
+    Ok(())
+}
+
+// The functions in `block` in the order in which they start, with their syntax,
+// for analyses that need more than `FunctionSpan`.
+pub fn function_nodes<'a>(
+    block: &'a Block<'a>,
+) -> Result<Vec<(FunctionSpan<'a>, FunctionNode<'a>)>, AstError<'a>> {
+    struct Nodes<'a>(Vec<(FunctionSpan<'a>, FunctionNode<'a>)>);
+
+    impl<'a> FunctionVisitor<'a> for Nodes<'a> {
+        fn enter_function(&mut self, span: &FunctionSpan<'a>, node: &FunctionNode<'a>) {
+            self.0.push((span.clone(), *node));
+        }
+    }
+
+    let mut nodes = Nodes(Vec::new());
+    visit_functions(block, &mut nodes)?;
+    Ok(nodes.0)
+}
+
+// Finds the functions in `block` in the order in which they start,
+// so that a function comes before the functions nested in it.
+pub fn gather_function_line_spans<'a>(
+    block: &'a Block<'a>,
+    functions: &mut Vec<FunctionSpan<'a>>,
+) -> Result<(), AstError<'a>> {
+    visit_functions(block, functions)
+}
+
+// Lines on which a statement in `block` or one of its nested control blocks begins.
+// Lines in nested function bodies are not included: they belong to those functions.
+// This approximates the lines that LuaCov reports hits for.
+pub fn executable_lines(block: &Block) -> Vec<usize> {
+    let mut lines = Vec::new();
+    // The order does not matter because the lines are sorted afterwards.
+    let mut blocks = vec![block];
+    while let Some(block) = blocks.pop() {
+        for statement in block.iter_stmts() {
+            lines.push(start_line(statement.first_token()));
+            match statement {
+                Stmt::Do(do_stmt) => blocks.push(do_stmt.block()),
+                Stmt::GenericFor(for_stmt) => blocks.push(for_stmt.block()),
+                Stmt::NumericFor(for_stmt) => blocks.push(for_stmt.block()),
+                Stmt::While(while_stmt) => blocks.push(while_stmt.block()),
+                Stmt::Repeat(repeat_stmt) => {
+                    blocks.push(repeat_stmt.block());
+                    lines.push(start_line(repeat_stmt.until_token()));
+                }
+                Stmt::If(if_stmt) => {
+                    blocks.push(if_stmt.block());
+                    if let Some(else_ifs) = if_stmt.else_if() {
+                        for else_if in else_ifs {
+                            lines.push(start_line(else_if.else_if_token()));
+                            blocks.push(else_if.block());
+                        }
+                    }
+                    if let Some(block) = if_stmt.else_block() {
+                        blocks.push(block);
+                    }
+                }
+                Stmt::Assignment(_)
+                | Stmt::FunctionCall(_)
+                | Stmt::FunctionDeclaration(_)
+                | Stmt::LocalAssignment(_)
+                | Stmt::LocalFunction(_) => {}
+                #[cfg(feature = "luau")]
+                Stmt::CompoundAssignment(_) | Stmt::Continue(_) | Stmt::TypeDeclaration(_) => {}
             }
-            Stmt::Repeat(repeat_stmt) => {
-                gather_function_line_spans(repeat_stmt.block(), functions)?;
+        }
+        if let Some(last_stmt) = block.last_stmt() {
+            lines.push(start_line(last_stmt.first_token()));
+        }
+    }
+    lines.sort_unstable();
+    lines.dedup();
+    lines
+}
+
+fn returned_name<'a>(block: &'a Block<'a>) -> Option<String> {
+    let returns = match block.last_stmt()? {
+        LastStmt::Return(ret) => ret.returns(),
+        LastStmt::Break(_) => return None,
+    };
+    let mut returns = returns.iter();
+    match (returns.next().map(strip_parentheses), returns.next()) {
+        (Some(UsefulExpression::Single(Value::Var(Var::Name(name)))), None) => {
+            Some(name.token().to_string())
+        }
+        _ => None,
+    }
+}
+
+// Decodes Lua source, which is bytes in no particular encoding, replacing each byte that is not
+// part of valid UTF-8 with `?`. Unlike `String::from_utf8_lossy`, this keeps byte offsets and
+// line numbers the same as in `code`, and columns too, if each invalid byte is taken as a character.
+// Invalid bytes are usually in strings or comments, where the replacement does not change
+// which functions are found.
+pub fn decode_source(code: &[u8]) -> Cow<'_, str> {
+    let mut rest = match std::str::from_utf8(code) {
+        Ok(code) => return Cow::Borrowed(code),
+        Err(_) => code,
+    };
+    let mut decoded = String::with_capacity(code.len());
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                decoded.push_str(valid);
+                break;
             }
-            Stmt::While(while_stmt) => {
-                gather_function_line_spans(while_stmt.block(), functions)?;
+            Err(e) => {
+                let (valid, invalid) = rest.split_at(e.valid_up_to());
+                // `valid` is valid UTF-8.
+                decoded.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                let invalid_len = e.error_len().unwrap_or(invalid.len());
+                decoded.extend(std::iter::repeat_n('?', invalid_len));
+                rest = &invalid[invalid_len..];
             }
-            Stmt::If(if_stmt) => {
-                gather_function_line_spans(if_stmt.block(), functions)?;
-                if let Some(blocks) = if_stmt.else_if() {
-                    for block in blocks {
-                        gather_function_line_spans(block.block(), functions)?;
-                    }
-                }
-                if let Some(block) = if_stmt.else_block() {
-                    gather_function_line_spans(block, functions)?;
-                }
+        }
+    }
+    Cow::Owned(decoded)
+}
+
+// A line of source code, split the same way Lua counts lines.
+#[derive(Debug, Clone, Copy)]
+pub struct SourceLine<'a> {
+    // byte offset of the start of the line
+    pub offset: usize,
+    // text without the line break
+    pub text: &'a str,
+}
+
+// Length of the line break at the start of `bytes`, or 0 if there is none.
+// Like the Lua lexer, this takes `\n`, `\r`, `\r\n` and `\n\r` each as one line break.
+fn line_break_len(bytes: &[u8]) -> usize {
+    match bytes {
+        [first @ (b'\n' | b'\r'), second @ (b'\n' | b'\r'), ..] if first != second => 2,
+        [b'\n' | b'\r', ..] => 1,
+        _ => 0,
+    }
+}
+
+// Lines of `code`, as numbered by the Lua interpreter. A UTF-8 byte order mark is not
+// part of the first line.
+pub fn source_lines(code: &str) -> Vec<SourceLine<'_>> {
+    let bytes = code.as_bytes();
+    let mut start = if code.starts_with('\u{feff}') { '\u{feff}'.len_utf8() } else { 0 };
+    let mut lines = vec![];
+    let mut i = start;
+    while i < bytes.len() {
+        match line_break_len(&bytes[i..]) {
+            0 => i += 1,
+            len => {
+                lines.push(SourceLine { offset: start, text: &code[start..i] });
+                i += len;
+                start = i;
             }
         }
     }
-    Ok(())
+    lines.push(SourceLine { offset: start, text: &code[start..] });
+    lines
+}
+
+// Rewrites `code` so that full_moon, which only knows `\n` line breaks, numbers lines
+// the way the Lua interpreter does: a byte order mark is removed, a first line starting
+// with `#`, usually `#!` for Unix scripts, is left empty, and every line break becomes `\n`.
+pub fn normalize_source(code: &str) -> Cow<'_, str> {
+    if !code.starts_with(['\u{feff}', '#']) && !code.contains('\r') {
+        return Cow::Borrowed(code);
+    }
+    let mut normalized = String::with_capacity(code.len());
+    for (i, line) in source_lines(code).into_iter().enumerate() {
+        if i > 0 {
+            normalized.push('\n');
+        }
+        if !(i == 0 && line.text.starts_with('#')) {
+            normalized.push_str(line.text);
+        }
+    }
+    Cow::Owned(normalized)
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct FunctionNameLine {
     pub start: usize,
     pub end: usize,
+    pub start_column: usize,
+    pub end_column: usize,
+    // as in `FunctionSpan`
+    pub name_start: (usize, usize),
+    pub name_end: (usize, usize),
     pub name: String,
+    pub segments: Vec<String>,
+    pub kind: FunctionKind,
+    // index of the enclosing function in `Module::function_record`
+    pub parent: Option<usize>,
+    pub executable_lines: Vec<usize>,
+    pub fingerprint: u64,
+    // hash of the names of the function and the functions enclosing it and of its fingerprint,
+    // distinguishing functions for which these are the same by their order
+    pub id: u64,
+    pub parameters: Vec<Parameter>,
+    // Luau return type annotation
+    pub return_type: Option<String>,
+    pub doc: Option<DocComment>,
+}
+
+impl FunctionNameLine {
+    // Whether the function contains the character at `line` and `column`.
+    pub fn contains(&self, line: usize, column: usize) -> bool {
+        (self.start, self.start_column) <= (line, column) && (line, column) < (self.end, self.end_column)
+    }
+
+    // Whether any part of the function is on `line`.
+    pub fn contains_line(&self, line: usize) -> bool {
+        (self.start..=self.end).contains(&line)
+    }
+
+    // Name, parameters and return type, like `M.f(a: number, b: string?): boolean`.
+    // Anonymous functions are named `function`.
+    pub fn signature(&self) -> String {
+        let mut signature = match self.kind {
+            FunctionKind::Anonymous => "function".to_string(),
+            _ => self.name.clone(),
+        };
+        signature.push('(');
+        for (i, parameter) in self.parameters.iter().enumerate() {
+            if i > 0 {
+                signature.push_str(", ");
+            }
+            signature.push_str(&parameter.name);
+            if let Some(type_annotation) = &parameter.type_annotation {
+                signature.push_str(": ");
+                signature.push_str(type_annotation);
+            }
+        }
+        signature.push(')');
+        if let Some(return_type) = &self.return_type {
+            signature.push_str(": ");
+            signature.push_str(return_type);
+        }
+        signature
+    }
 }
 
 impl<'a> From<FunctionSpan<'a>> for FunctionNameLine {
-    fn from(FunctionSpan { start, end, name }: FunctionSpan<'a>) -> Self {
+    fn from(
+        FunctionSpan {
+            start,
+            end,
+            start_column,
+            end_column,
+            name_start,
+            name_end,
+            name,
+            executable_lines,
+            fingerprint,
+            parameters,
+            return_type,
+            doc,
+        }: FunctionSpan<'a>,
+    ) -> Self {
         FunctionNameLine {
             start,
             end,
+            start_column,
+            end_column,
+            name_start,
+            name_end,
             name: name.to_string(),
+            segments: name.segments(),
+            kind: name.kind(),
+            parent: None,
+            executable_lines,
+            fingerprint,
+            id: 0,
+            parameters,
+            return_type,
+            doc,
         }
     }
 }
 
+// Number of the first line of a file. Lua, and so `FunctionNameLine` and `Module::executable_lines`,
+// number lines from one, while many editors and C APIs number them from zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum LineBase {
+    Zero,
+    #[default]
+    One,
+}
+
+impl LineBase {
+    // One-based number of `line`, which is numbered from this base,
+    // or `None` for zero when lines are numbered from one.
+    pub fn to_one_based(self, line: usize) -> Option<usize> {
+        match self {
+            LineBase::Zero => line.checked_add(1),
+            LineBase::One => line.checked_sub(1).map(|_| line),
+        }
+    }
+
+    // Number of the one-based `line` in this base.
+    pub fn from_one_based(self, line: usize) -> usize {
+        match self {
+            LineBase::Zero => line.saturating_sub(1),
+            LineBase::One => line,
+        }
+    }
+}
+
+// Version of Lua that the code is written in. Luau needs the `luau` feature, which enables
+// full_moon's `roblox` feature, and is `ErrorKind::Unsupported` without it.
+// The version of full_moon in use has no grammar for Lua 5.2 and later or for LuaJIT,
+// so they are always `ErrorKind::Unsupported`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Dialect {
+    #[default]
+    Lua51,
+    Lua52,
+    Lua53,
+    Lua54,
+    LuaJit,
+    Luau,
+}
+
+impl Dialect {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Dialect::Lua51 => "Lua 5.1",
+            Dialect::Lua52 => "Lua 5.2",
+            Dialect::Lua53 => "Lua 5.3",
+            Dialect::Lua54 => "Lua 5.4",
+            Dialect::LuaJit => "LuaJIT",
+            Dialect::Luau => "Luau",
+        }
+    }
+
+    fn check_supported(self) -> Result<(), Error> {
+        let message = match self {
+            Dialect::Lua51 => return Ok(()),
+            Dialect::Luau if cfg!(feature = "luau") => return Ok(()),
+            Dialect::Luau => "Luau needs the `luau` feature".to_string(),
+            _ => format!("{} cannot be parsed yet", self.as_str()),
+        };
+        Err(Error {
+            kind: ErrorKind::Unsupported,
+            message,
+            position: None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Options {
+    // Code nested more deeply than this is rejected with `ErrorKind::Nesting`.
+    // Each block, pair of brackets, and operator in a chain of operators counts as a level.
+    // full_moon parses recursively, with up to about 40 KB of stack per level in release builds
+    // and 256 KB in debug builds, so the default suits the 8 MB stack of a main thread
+    // in a release build.
+    pub max_depth: usize,
+    // If set, the code is parsed on a new thread with a stack of this many bytes,
+    // for a `max_depth` that the stack of the caller is too small for.
+    pub parser_stack_size: Option<usize>,
+    // how the `line` arguments of `Module::get_function`, `Module::get_function_at`
+    // and `Module::get_function_stack` are numbered
+    pub line_base: LineBase,
+    pub dialect: Dialect,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            max_depth: 150,
+            parser_stack_size: None,
+            line_base: LineBase::One,
+            dialect: Dialect::Lua51,
+        }
+    }
+}
+
+// Checks that the code is not nested more deeply than `max_depth` (see `Options::max_depth`)
+// and returns how deeply it is nested.
+// Runs of operators are counted, because full_moon parses `a .. b .. c` as nested expressions.
+// A run ends where a new expression must start, so the count may be too high, but never too low.
+fn nesting_depth(tokens: &[Token], max_depth: usize) -> Result<usize, Error> {
+    // depth outside of the current block or brackets, and the operators before it
+    let mut enclosing: Vec<(usize, usize)> = Vec::new();
+    let (mut depth, mut operators, mut max) = (0, 0, 0);
+    let mut after_operand = false;
+    for token in tokens.iter().filter(|token| !token.token_type().is_trivia()) {
+        let symbol = match token.token_type() {
+            TokenType::Symbol { symbol } => Some(*symbol),
+            _ => None,
+        };
+        let operand = match symbol {
+            None => !matches!(token.token_type(), TokenType::Eof),
+            Some(Symbol::Nil | Symbol::True | Symbol::False | Symbol::Ellipse) => true,
+            _ => false,
+        };
+        match symbol {
+            Some(
+                Symbol::Function
+                | Symbol::Do
+                | Symbol::If
+                | Symbol::Repeat
+                | Symbol::LeftParen
+                | Symbol::LeftBrace
+                | Symbol::LeftBracket,
+            ) => {
+                enclosing.push((depth, operators));
+                depth += operators + 1;
+                operators = 0;
+            }
+            Some(
+                Symbol::End
+                | Symbol::Until
+                | Symbol::RightParen
+                | Symbol::RightBrace
+                | Symbol::RightBracket,
+            ) => {
+                let (outer_depth, outer_operators) = enclosing.pop().unwrap_or((0, 0));
+                depth = outer_depth;
+                operators = outer_operators;
+            }
+            Some(
+                Symbol::And
+                | Symbol::Or
+                | Symbol::Not
+                | Symbol::Caret
+                | Symbol::TwoDots
+                | Symbol::TwoEqual
+                | Symbol::TildeEqual
+                | Symbol::GreaterThan
+                | Symbol::GreaterThanEqual
+                | Symbol::LessThan
+                | Symbol::LessThanEqual
+                | Symbol::Hash
+                | Symbol::Minus
+                | Symbol::Plus
+                | Symbol::Star
+                | Symbol::Slash
+                | Symbol::Percent,
+            ) => operators += 1,
+            // Two operands in a row start a new statement.
+            _ if operand => {
+                if after_operand {
+                    operators = 0;
+                }
+            }
+            Some(Symbol::Dot | Symbol::Colon) => {}
+            // keywords and punctuation that start a new expression
+            _ => operators = 0,
+        }
+        after_operand = operand
+            || matches!(
+                symbol,
+                Some(Symbol::End | Symbol::RightParen | Symbol::RightBrace | Symbol::RightBracket)
+            );
+        if depth + operators > max {
+            max = depth + operators;
+            if max > max_depth {
+                return Err(Error {
+                    kind: ErrorKind::Nesting,
+                    message: format!("code is nested more than {} levels deep", max_depth),
+                    position: Some((start_line(token), start_column(token))),
+                });
+            }
+        }
+    }
+    Ok(max)
+}
+
+#[derive(Debug, Clone)]
 #[repr(C)]
 pub struct Module {
     function_record: Vec<FunctionNameLine>,
+    // executable lines of the main chunk, outside of any function
+    executable_lines: Vec<usize>,
+    line_base: LineBase,
+    returned_name: Option<String>,
 }
 
 impl Module {
     pub fn new(code: &str) -> Option<Self> {
-        Some(Self { function_record: Self::get_function_record(code).ok()? })
+        Self::parse(code).ok()
+    }
+
+    pub fn parse(code: &str) -> Result<Self, Error> {
+        Self::parse_with(code, &Options::default())
+    }
+
+    // Code nested more deeply than `options.max_depth` is rejected before it is parsed,
+    // so that it cannot overflow the stack.
+    pub fn parse_with(code: &str, options: &Options) -> Result<Self, Error> {
+        options.dialect.check_supported()?;
+        let code = &*normalize_source(code);
+        let tokens = full_moon::tokenizer::tokens(code).map_err(full_moon::Error::TokenizerError)?;
+        nesting_depth(&tokens, options.max_depth)?;
+        let stack_size = match options.parser_stack_size {
+            Some(stack_size) => stack_size,
+            None => return Self::parse_on_this_thread(tokens, options),
+        };
+        std::thread::scope(|scope| {
+            let parser = std::thread::Builder::new()
+                .name("lua-function-at-line parser".to_string())
+                .stack_size(stack_size)
+                .spawn_scoped(scope, || Self::parse_on_this_thread(tokens, options))
+                .map_err(|e| Error {
+                    kind: ErrorKind::Resource,
+                    message: format!("could not start a thread to parse with a large stack: {}", e),
+                    position: None,
+                })?;
+            parser
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
     }
 
-    fn get_function_record<'a>(code: &'a str) -> Result<Vec<FunctionNameLine>, String> {
-        let ast = full_moon::parse(&code).map_err(|e| e.to_string())?;
+    fn parse_on_this_thread<'a>(tokens: Vec<Token<'a>>, options: &Options) -> Result<Self, Error> {
+        let ast = Ast::from_tokens(tokens).map_err(full_moon::Error::AstError)?;
+        Self::from_block_with(ast.nodes(), options)
+    }
+
+    // For code already parsed with full_moon. Line numbers are those of the AST, so they match
+    // Lua's only if the code was parsed after `normalize_source`.
+    pub fn from_ast<'a>(ast: &'a Ast<'a>) -> Result<Self, Error> {
+        Self::from_block(ast.nodes())
+    }
+
+    pub fn from_block<'a>(block: &'a Block<'a>) -> Result<Self, Error> {
+        Self::from_block_with(block, &Options::default())
+    }
+
+    // `options.max_depth` does not apply, since the code has been parsed already.
+    pub fn from_block_with<'a>(block: &'a Block<'a>, options: &Options) -> Result<Self, Error> {
+        options.dialect.check_supported()?;
+        #[cfg(feature = "luau")]
+        if options.dialect != Dialect::Luau {
+            luau::check_not_luau(block, options.dialect)?;
+        }
         let mut functions = vec![];
-        gather_function_line_spans(ast.nodes(), &mut functions).map_err(|e| e.to_string())?;
-        Ok(functions.into_iter().map(FunctionNameLine::from).collect())
+        gather_function_line_spans(block, &mut functions)?;
+        let mut function_record: Vec<_> =
+            functions.into_iter().map(FunctionNameLine::from).collect();
+        // Functions are found before the functions nested in them.
+        let mut enclosing: Vec<usize> = Vec::new();
+        for i in 0..function_record.len() {
+            let (start, start_column) = (function_record[i].start, function_record[i].start_column);
+            while let Some(&parent) = enclosing.last() {
+                if function_record[parent].contains(start, start_column) {
+                    break;
+                }
+                enclosing.pop();
+            }
+            function_record[i].parent = enclosing.last().copied();
+            enclosing.push(i);
+        }
+        // The ids of nested functions depend on the names of the enclosing functions
+        // but not on their bodies, so that editing a function keeps the ids of the functions in it.
+        let mut name_paths = Vec::with_capacity(function_record.len());
+        let mut ids = std::collections::HashSet::new();
+        for function in &mut function_record {
+            let name_path = match function.parent {
+                Some(parent) => name_paths[parent],
+                None => FNV_OFFSET_BASIS,
+            };
+            let name_path = fnv1a(fnv1a(name_path, function.name.as_bytes()), &[0]);
+            name_paths.push(name_path);
+            let hash = fnv1a(name_path, &function.fingerprint.to_le_bytes());
+            let mut id = hash;
+            let mut duplicate = 0u64;
+            while !ids.insert(id) {
+                duplicate += 1;
+                id = fnv1a(hash, &duplicate.to_le_bytes());
+            }
+            function.id = id;
+        }
+        Ok(Self {
+            function_record,
+            executable_lines: executable_lines(block),
+            line_base: options.line_base,
+            returned_name: returned_name(block),
+        })
+    }
+
+    // Like `parse`, but for code that may not be valid UTF-8. See `decode_source`.
+    pub fn parse_bytes(code: &[u8]) -> Result<Self, Error> {
+        Self::parse(&decode_source(code))
     }
 
+    // Name of the innermost function with some part on `line`. A function includes the lines
+    // of its `function` keyword and its `end`.
     pub fn get_function(&self, line: usize) -> Option<&str> {
+        let line = self.line_base.to_one_based(line)?;
         self.function_record
             .iter()
-            .filter(|FunctionNameLine { start, end, .. }| (*start..*end).contains(&line))
-            .last()
+            .rev()
+            .find(|function| function.contains_line(line))
             .map(|name_line| name_line.name.as_ref())
     }
 
+    // Innermost function containing the character at `line` and `column`.
+    pub fn get_function_at(&self, line: usize, column: usize) -> Option<&FunctionNameLine> {
+        self.get_function_index_at(line, column).map(|i| &self.function_record[i])
+    }
+
+    // Like `get_function_at`, but the index of the function in `function_record`.
+    pub fn get_function_index_at(&self, line: usize, column: usize) -> Option<usize> {
+        let line = self.line_base.to_one_based(line)?;
+        self.function_record
+            .iter()
+            .rposition(|function| function.contains(line, column))
+    }
+
+    // Functions with some part on `line`, from outermost to innermost.
+    // Several functions on the same line that are not nested in each other are all included.
+    pub fn get_function_stack(&self, line: usize) -> Vec<&FunctionNameLine> {
+        self.get_function_stack_indices(line)
+            .into_iter()
+            .map(|i| &self.function_record[i])
+            .collect()
+    }
+
+    // Like `get_function_stack`, but the indices of the functions in `function_record`.
+    pub fn get_function_stack_indices(&self, line: usize) -> Vec<usize> {
+        let line = match self.line_base.to_one_based(line) {
+            Some(line) => line,
+            None => return Vec::new(),
+        };
+        (0..self.function_record.len())
+            .filter(|&i| self.function_record[i].contains_line(line))
+            .collect()
+    }
+
+    // How the `line` arguments of the lookup methods are numbered. See `Options::line_base`.
+    pub fn line_base(&self) -> LineBase {
+        self.line_base
+    }
+
     pub fn function_record(&self) -> &[FunctionNameLine] {
         &self.function_record
     }
+
+    pub fn executable_lines(&self) -> &[usize] {
+        &self.executable_lines
+    }
+
+    // Name of the variable that the main chunk returns, as in `return M`,
+    // which is usually the table of the functions that a module exports.
+    pub fn returned_name(&self) -> Option<&str> {
+        self.returned_name.as_deref()
+    }
 }
 
 mod tests;
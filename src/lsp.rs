@@ -0,0 +1,340 @@
+// A language server that provides document symbols and folding ranges for functions,
+// plus the `luaFunctionAtLine/enclosingFunction` request for statuslines.
+// It communicates with JSON-RPC over a pair of streams, usually stdin and stdout.
+
+use crate::{source_lines, FunctionKind, FunctionNameLine, Module, SourceLine};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+pub const ENCLOSING_FUNCTION: &str = "luaFunctionAtLine/enclosingFunction";
+
+// LSP `SymbolKind`
+const SYMBOL_KIND_METHOD: u32 = 6;
+const SYMBOL_KIND_FUNCTION: u32 = 12;
+
+// Longest message that is read, so that a bad header cannot make the server allocate
+// any amount of memory.
+const MAX_CONTENT_LENGTH: usize = 64 * 1024 * 1024;
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_PARAMS: i64 = -32602;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+// Character offset of a (1-based) column in UTF-16 code units, as LSP requires.
+fn utf16_offset(line: &str, column: usize) -> usize {
+    line.chars()
+        .take(column.saturating_sub(1))
+        .map(char::len_utf16)
+        .sum()
+}
+
+// 1-based column of a character offset in UTF-16 code units.
+fn column(line: &str, utf16_offset: usize) -> usize {
+    let mut offset = 0;
+    let mut column = 1;
+    for c in line.chars() {
+        offset += c.len_utf16();
+        if offset > utf16_offset {
+            break;
+        }
+        column += 1;
+    }
+    column
+}
+
+struct Document {
+    text: String,
+    // result of the last successful analysis, kept while the text has syntax errors
+    module: Option<Module>,
+}
+
+impl Document {
+    fn new(text: String) -> Self {
+        let mut document = Document { text, module: None };
+        document.analyze();
+        document
+    }
+
+    fn analyze(&mut self) {
+        if let Ok(module) = Module::parse(&self.text) {
+            self.module = Some(module);
+        }
+    }
+
+    fn lines(&self) -> Vec<SourceLine<'_>> {
+        source_lines(&self.text)
+    }
+
+    fn functions(&self) -> &[FunctionNameLine] {
+        self.module
+            .as_ref()
+            .map_or(&[], |module| module.function_record())
+    }
+}
+
+fn position(lines: &[SourceLine], line: usize, column: usize) -> Value {
+    let text = lines.get(line - 1).map_or("", |line| line.text);
+    json!({ "line": line - 1, "character": utf16_offset(text, column) })
+}
+
+fn range(lines: &[SourceLine], function: &FunctionNameLine) -> Value {
+    json!({
+        "start": position(lines, function.start, function.start_column),
+        "end": position(lines, function.end, function.end_column),
+    })
+}
+
+fn selection_range(lines: &[SourceLine], function: &FunctionNameLine) -> Value {
+    let ((start, start_column), (end, end_column)) = (function.name_start, function.name_end);
+    json!({
+        "start": position(lines, start, start_column),
+        "end": position(lines, end, end_column),
+    })
+}
+
+fn document_symbols(document: &Document) -> Value {
+    fn symbol(lines: &[SourceLine], functions: &[FunctionNameLine], i: usize) -> Value {
+        let function = &functions[i];
+        let children = functions
+            .iter()
+            .enumerate()
+            .filter(|(_, child)| child.parent == Some(i))
+            .map(|(j, _)| symbol(lines, functions, j))
+            .collect::<Vec<_>>();
+        let kind = match function.kind {
+            FunctionKind::Method => SYMBOL_KIND_METHOD,
+            _ => SYMBOL_KIND_FUNCTION,
+        };
+        json!({
+            "name": function.name,
+            "kind": kind,
+            "range": range(lines, function),
+            "selectionRange": selection_range(lines, function),
+            "children": children,
+        })
+    }
+
+    let lines = document.lines();
+    let functions = document.functions();
+    let symbols = functions
+        .iter()
+        .enumerate()
+        .filter(|(_, function)| function.parent.is_none())
+        .map(|(i, _)| symbol(&lines, functions, i))
+        .collect();
+    Value::Array(symbols)
+}
+
+// Folds from the line where each function starts up to the line before its `end`.
+fn folding_ranges(document: &Document) -> Value {
+    let ranges = document
+        .functions()
+        .iter()
+        .filter(|function| function.end >= function.start + 2)
+        .map(|function| json!({ "startLine": function.start - 1, "endLine": function.end - 2 }))
+        .collect();
+    Value::Array(ranges)
+}
+
+fn enclosing_function(document: &Document, line: usize, character: usize) -> Value {
+    let lines = document.lines();
+    let text = lines.get(line).map_or("", |line| line.text);
+    let (line, column) = (line + 1, column(text, character));
+    let functions = document.functions();
+    let innermost = functions
+        .iter()
+        .rposition(|function| function.contains(line, column));
+    match innermost {
+        Some(i) => {
+            let mut stack = vec![functions[i].name.as_str()];
+            let mut parent = functions[i].parent;
+            while let Some(i) = parent {
+                stack.push(&functions[i].name);
+                parent = functions[i].parent;
+            }
+            stack.reverse();
+            json!({
+                "name": functions[i].name,
+                "range": range(&lines, &functions[i]),
+                "stack": stack,
+            })
+        }
+        None => Value::Null,
+    }
+}
+
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut content_length = None;
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let content_length = content_length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length header")
+    })?;
+    if content_length > MAX_CONTENT_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Content-Length {} is more than {}", content_length, MAX_CONTENT_LENGTH),
+        ));
+    }
+    let mut content = vec![0; content_length];
+    input.read_exact(&mut content)?;
+    Ok(Some(content))
+}
+
+fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+    write!(
+        output,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    output.flush()
+}
+
+struct Server<W> {
+    output: W,
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+impl<W: Write> Server<W> {
+    fn respond(&mut self, id: Value, result: Result<Value, (i64, String)>) -> io::Result<()> {
+        let message = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message },
+            }),
+        };
+        write_message(&mut self.output, &message)
+    }
+
+    fn document(&self, params: &Value) -> Result<&Document, (i64, String)> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .ok_or_else(|| (INVALID_PARAMS, "missing textDocument.uri".to_string()))?;
+        self.documents
+            .get(uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("unknown document {}", uri)))
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    // full document sync
+                    "textDocumentSync": 1,
+                    "documentSymbolProvider": true,
+                    "foldingRangeProvider": true,
+                    "experimental": { "enclosingFunctionProvider": true },
+                },
+                "serverInfo": {
+                    "name": "lua-function-at-line",
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/documentSymbol" => Ok(document_symbols(self.document(params)?)),
+            "textDocument/foldingRange" => Ok(folding_ranges(self.document(params)?)),
+            ENCLOSING_FUNCTION => {
+                let document = self.document(params)?;
+                let position = &params["position"];
+                match (position["line"].as_u64(), position["character"].as_u64()) {
+                    (Some(line), Some(character)) => Ok(enclosing_function(
+                        document,
+                        line as usize,
+                        character as usize,
+                    )),
+                    _ => Err((INVALID_PARAMS, "missing position".to_string())),
+                }
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) {
+        let uri = params["textDocument"]["uri"].as_str().map(String::from);
+        match (method, uri) {
+            ("textDocument/didOpen", Some(uri)) => {
+                if let Some(text) = params["textDocument"]["text"].as_str() {
+                    self.documents.insert(uri, Document::new(text.to_string()));
+                }
+            }
+            ("textDocument/didChange", Some(uri)) => {
+                // With full document sync, the last change contains the whole text.
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                if let (Some(document), Some(text)) = (self.documents.get_mut(&uri), text) {
+                    document.text = text.to_string();
+                    document.analyze();
+                }
+            }
+            ("textDocument/didClose", Some(uri)) => {
+                self.documents.remove(&uri);
+            }
+            _ => {}
+        }
+    }
+}
+
+// Serves requests until the client sends `exit`.
+// Returns whether the client asked the server to shut down first,
+// which determines the exit status of the server process.
+pub fn run<R: BufRead, W: Write>(mut input: R, output: W) -> io::Result<bool> {
+    let mut server = Server {
+        output,
+        documents: HashMap::new(),
+        shutdown: false,
+    };
+    while let Some(content) = read_message(&mut input)? {
+        let message: Value = match serde_json::from_slice(&content) {
+            Ok(message) => message,
+            Err(e) => {
+                server.respond(Value::Null, Err((PARSE_ERROR, e.to_string())))?;
+                continue;
+            }
+        };
+        // Responses from the client are ignored, because the server sends no requests.
+        let method = match message["method"].as_str() {
+            Some(method) => method,
+            None => continue,
+        };
+        if method == "exit" {
+            break;
+        }
+        let params = &message["params"];
+        match message.get("id") {
+            Some(id) => {
+                let result = server.request(method, params);
+                server.respond(id.clone(), result)?;
+            }
+            None => server.notification(method, params),
+        }
+    }
+    Ok(server.shutdown)
+}
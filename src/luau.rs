@@ -0,0 +1,67 @@
+// With the `luau` feature, full_moon parses Luau, which is a superset of Lua 5.1.
+// Code in the other dialects is checked for the syntax that only Luau has.
+
+use crate::{unexpected_token, Dialect, Error, ErrorKind};
+use full_moon::{
+    ast::{
+        types::{AsAssertion, CompoundAssignment, TypeDeclaration, TypeSpecifier},
+        Block, Stmt,
+    },
+    node::Node,
+    visitors::{Visit, Visitor},
+};
+
+struct LuauSyntax {
+    dialect: Dialect,
+    // the first Luau construct in the code
+    found: Option<Error>,
+}
+
+impl LuauSyntax {
+    fn found<'ast>(&mut self, node: &impl Node<'ast>, description: &str) {
+        if self.found.is_some() {
+            return;
+        }
+        if let Some(token) = node.tokens().next() {
+            let message = format!("{} is Luau syntax, not {}", description, self.dialect.as_str());
+            let error = unexpected_token(&token, &message);
+            self.found = Some(Error::from_ast_error(ErrorKind::Parse, error));
+        }
+    }
+}
+
+impl<'ast> Visitor<'ast> for LuauSyntax {
+    fn visit_stmt(&mut self, stmt: &Stmt<'ast>) {
+        if let Stmt::Continue(token) = stmt {
+            self.found(token.as_ref(), "`continue`");
+        }
+    }
+
+    fn visit_compound_assignment(&mut self, assignment: &CompoundAssignment<'ast>) {
+        self.found(assignment.compound_operator(), "compound assignment");
+    }
+
+    fn visit_type_declaration(&mut self, declaration: &TypeDeclaration<'ast>) {
+        self.found(declaration, "a type declaration");
+    }
+
+    fn visit_type_specifier(&mut self, specifier: &TypeSpecifier<'ast>) {
+        self.found(specifier, "a type annotation");
+    }
+
+    fn visit_as_assertion(&mut self, assertion: &AsAssertion<'ast>) {
+        self.found(assertion, "a type assertion");
+    }
+}
+
+pub(crate) fn check_not_luau(block: &Block, dialect: Dialect) -> Result<(), Error> {
+    let mut visitor = LuauSyntax {
+        dialect,
+        found: None,
+    };
+    block.visit(&mut visitor);
+    match visitor.found {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
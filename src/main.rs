@@ -0,0 +1,508 @@
+use lua_function_at_line::{
+    diff::{diff, ChangeKind},
+    docgen::{write_html, write_markdown, DocSource},
+    hunks::{file_count, rewrite_hunk_headers, FilePair},
+    tags::{write_ctags, write_etags, TagSource},
+    decode_source, source_lines, FunctionNameLine, Module,
+};
+use std::{
+    fmt::Display,
+    io::Write,
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+const USAGE: &str = "\
+usage: lua-function-at-line <command> [arguments]
+
+commands:
+    list [--json|--ndjson] FILE...
+                            list the functions in each file, optionally as a
+                            JSON array or as one JSON object per line
+    at FILE:LINE[:COLUMN]   print the innermost function at a position
+    stack FILE:LINE         print the functions with some part on a line,
+                            outermost first, including functions side by side
+                            on the line that do not enclose each other
+    annotate FILE           print each line with the name of its function
+    tags [-e] [-o OUTPUT] PATH...
+                            write a tag file for the Lua files in each path,
+                            searching directories recursively; `-e` writes
+                            Emacs tags (default output `TAGS`) instead of
+                            ctags (default output `tags`), `-o -` writes to
+                            standard output
+    docs [--html] [-o OUTPUT] PATH...
+                            write Markdown, or HTML with `--html`, documenting
+                            the functions that the Lua files in each path
+                            export, with their doc comments; the default
+                            output is standard output
+    diff OLD NEW            list the functions that were added, removed,
+                            moved, renamed or modified between two versions
+                            of a file, followed by a summary
+    hunk-headers [--old FILE] [--new FILE] [DIFF]
+                            rewrite the hunk headers of a unified diff (read
+                            from DIFF or standard input) with the function
+                            enclosing each hunk; the new version of each Lua
+                            file is read from the working tree, the old
+                            version is not used; for a diff of one file,
+                            `--old` and `--new` give the versions to use
+
+Lines and columns start at 1.
+
+exit status:
+    0   success
+    1   no function at the given position
+    2   invalid arguments
+    3   a file could not be read or parsed";
+
+const EXIT_NOT_FOUND: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_FAILURE: i32 = 3;
+
+fn usage_error(message: impl Display) -> ! {
+    eprintln!("lua-function-at-line: {}\n\n{}", message, USAGE);
+    exit(EXIT_USAGE)
+}
+
+fn failure(file: &str, message: impl Display) -> ! {
+    eprintln!("lua-function-at-line: {}: {}", file, message);
+    exit(EXIT_FAILURE)
+}
+
+// Reads Lua source, which may not be valid UTF-8.
+fn read_source(path: impl AsRef<Path>) -> std::io::Result<String> {
+    std::fs::read(path).map(|code| decode_source(&code).into_owned())
+}
+
+fn read_file(file: &str) -> String {
+    read_source(file).unwrap_or_else(|e| failure(file, e))
+}
+
+fn parse_file(file: &str, code: &str) -> Module {
+    Module::parse(code).unwrap_or_else(|e| failure(file, e))
+}
+
+// Parses `FILE:LINE` or `FILE:LINE:COLUMN`. The file name may itself contain colons.
+fn parse_position(arg: &str, allow_column: bool) -> (&str, usize, Option<usize>) {
+    let invalid = || -> ! {
+        if allow_column {
+            usage_error(format!("expected FILE:LINE[:COLUMN], got `{}`", arg))
+        } else {
+            usage_error(format!("expected FILE:LINE, got `{}`", arg))
+        }
+    };
+    let number = |n: &str| n.parse::<usize>().ok().filter(|&n| n > 0);
+    let (rest, last) = arg.rsplit_once(':').unwrap_or_else(|| invalid());
+    let last = number(last).unwrap_or_else(|| invalid());
+    if allow_column {
+        if let Some((file, line)) = rest.rsplit_once(':') {
+            if let Some(line) = number(line) {
+                return (file, line, Some(last));
+            }
+        }
+    }
+    if rest.is_empty() {
+        invalid()
+    }
+    (rest, last, None)
+}
+
+fn print_function(function: &FunctionNameLine) {
+    println!(
+        "{}:{}..{}:{}\t{}",
+        function.start, function.start_column, function.end, function.end_column, function.name
+    );
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ListFormat {
+    Text,
+    Json,
+    Ndjson,
+}
+
+fn list(args: &[String]) {
+    let (format, files) = match args.split_first() {
+        Some((option, files)) if option == "--json" => (ListFormat::Json, files),
+        Some((option, files)) if option == "--ndjson" => (ListFormat::Ndjson, files),
+        _ => (ListFormat::Text, args),
+    };
+    if files.is_empty() {
+        usage_error("expected at least one file");
+    }
+    let modules = files
+        .iter()
+        .map(|file| (file.as_str(), parse_file(file, &read_file(file))));
+    match format {
+        ListFormat::Text => {
+            for (file, module) in modules {
+                for function in module.function_record() {
+                    if files.len() > 1 {
+                        print!("{}:", file);
+                    }
+                    print_function(function);
+                }
+            }
+        }
+        #[cfg(feature = "json")]
+        ListFormat::Json => {
+            let modules = modules.collect::<Vec<_>>();
+            let modules = modules.iter().map(|(file, module)| (Some(*file), module));
+            lua_function_at_line::json::write_document(std::io::stdout().lock(), modules)
+                .unwrap_or_else(|e| failure("<stdout>", e));
+            println!();
+        }
+        #[cfg(feature = "json")]
+        ListFormat::Ndjson => {
+            for (file, module) in modules {
+                lua_function_at_line::json::write_lines(
+                    std::io::stdout().lock(),
+                    Some(file),
+                    &module,
+                )
+                .unwrap_or_else(|e| failure(file, e));
+            }
+        }
+        #[cfg(not(feature = "json"))]
+        ListFormat::Json | ListFormat::Ndjson => {
+            usage_error("JSON output requires the `json` feature")
+        }
+    }
+}
+
+fn at(position: &str) {
+    let (file, line, column) = parse_position(position, true);
+    let module = parse_file(file, &read_file(file));
+    let function = match column {
+        Some(column) => module.get_function_at(line, column),
+        None => module.get_function_stack(line).last().copied(),
+    };
+    match function {
+        Some(function) => println!("{}", function.name),
+        None => exit(EXIT_NOT_FOUND),
+    }
+}
+
+fn stack(position: &str) {
+    let (file, line, _) = parse_position(position, false);
+    let module = parse_file(file, &read_file(file));
+    let stack = module.get_function_stack(line);
+    if stack.is_empty() {
+        exit(EXIT_NOT_FOUND);
+    }
+    for function in stack {
+        print_function(function);
+    }
+}
+
+fn annotate(file: &str) {
+    let code = read_file(file);
+    let module = parse_file(file, &code);
+    let functions = module.function_record();
+    let width = functions
+        .iter()
+        .map(|function| function.name.len())
+        .chain(std::iter::once("<chunk>".len()))
+        .max()
+        .unwrap_or(0);
+    let mut lines = source_lines(&code);
+    // There is no line after a final line break to show.
+    if lines.last().is_some_and(|line| line.text.is_empty()) {
+        lines.pop();
+    }
+    for (i, line) in lines.iter().enumerate() {
+        let name = module
+            .get_function_stack(i + 1)
+            .last()
+            .map_or("<chunk>", |function| &function.name);
+        println!("{: <5}{: >width$}  {}", i + 1, name, line.text, width = width);
+    }
+}
+
+// Lua files in `path`, which may be a file or a directory, in sorted order.
+// Symbolic links to directories inside `path` are not followed, so that a
+// link back up the tree does not make this recurse forever.
+fn lua_files(path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if path.is_dir() {
+        let mut entries = std::fs::read_dir(path)?
+            .map(|entry| entry.and_then(|entry| Ok((entry.path(), entry.file_type()?))))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (entry, file_type) in entries {
+            if file_type.is_dir() {
+                lua_files(&entry, files)?;
+            } else if !entry.is_dir()
+                && entry
+                    .extension()
+                    .is_some_and(|extension| extension == "lua")
+            {
+                files.push(entry);
+            }
+        }
+    } else {
+        files.push(path.to_owned());
+    }
+    Ok(())
+}
+
+// Parses the Lua files in `paths`, searching directories recursively. Files that cannot be
+// read or parsed are reported and left out, and the second value tells whether there were any.
+fn parse_paths(paths: &[String]) -> (Vec<(String, String, Module)>, bool) {
+    if paths.is_empty() {
+        usage_error("expected at least one path");
+    }
+    let mut files = Vec::new();
+    for path in paths {
+        lua_files(Path::new(path), &mut files).unwrap_or_else(|e| failure(path, e));
+    }
+    let mut failed = false;
+    let mut parsed = Vec::new();
+    for file in files {
+        let path = file.to_string_lossy().into_owned();
+        let code = match read_source(&file) {
+            Ok(code) => code,
+            Err(e) => {
+                eprintln!("lua-function-at-line: {}: {}", path, e);
+                failed = true;
+                continue;
+            }
+        };
+        match Module::parse(&code) {
+            Ok(module) => parsed.push((path, code, module)),
+            Err(e) => {
+                eprintln!("lua-function-at-line: {}: {}", path, e);
+                failed = true;
+            }
+        }
+    }
+    (parsed, failed)
+}
+
+fn tags(mut args: &[String]) {
+    let mut emacs = false;
+    let mut output = None;
+    loop {
+        match args {
+            [option, rest @ ..] if option == "-e" => {
+                emacs = true;
+                args = rest;
+            }
+            [option, file, rest @ ..] if option == "-o" => {
+                output = Some(file.as_str());
+                args = rest;
+            }
+            _ => break,
+        }
+    }
+    let (parsed, failed) = parse_paths(args);
+    let sources = parsed
+        .iter()
+        .map(|(path, code, module)| TagSource { path, code, module })
+        .collect::<Vec<_>>();
+    let output = output.unwrap_or(if emacs { "TAGS" } else { "tags" });
+    let mut writer: Box<dyn Write> = if output == "-" {
+        Box::new(std::io::stdout())
+    } else {
+        let file = std::fs::File::create(output).unwrap_or_else(|e| failure(output, e));
+        Box::new(std::io::BufWriter::new(file))
+    };
+    if emacs {
+        write_etags(&mut writer, &sources)
+    } else {
+        write_ctags(&mut writer, &sources)
+    }
+    .and_then(|()| writer.flush())
+    .unwrap_or_else(|e| failure(output, e));
+    if failed {
+        exit(EXIT_FAILURE);
+    }
+}
+
+fn function_diff(old_file: &str, new_file: &str) {
+    let old = parse_file(old_file, &read_file(old_file));
+    let new = parse_file(new_file, &read_file(new_file));
+    let changes = diff(&old, &new);
+    let lines = |function: Option<&FunctionNameLine>| {
+        function.map_or_else(
+            || "-".to_string(),
+            |function| format!("{}..{}", function.start, function.end),
+        )
+    };
+    let mut counts = [
+        (ChangeKind::Added, 0),
+        (ChangeKind::Removed, 0),
+        (ChangeKind::Moved, 0),
+        (ChangeKind::Renamed, 0),
+        (ChangeKind::Modified, 0),
+    ];
+    for change in changes {
+        if change.kind == ChangeKind::Unchanged {
+            continue;
+        }
+        let name = match (change.old, change.new) {
+            (Some(old), Some(new)) if old.name != new.name => {
+                format!("{} -> {}", old.name, new.name)
+            }
+            (_, Some(function)) | (Some(function), None) => function.name.clone(),
+            (None, None) => continue,
+        };
+        println!(
+            "{}\t{}\t{}\t{}",
+            change.kind.as_str(),
+            name,
+            lines(change.old),
+            lines(change.new)
+        );
+        for (kind, count) in counts.iter_mut() {
+            if *kind == change.kind {
+                *count += 1;
+            }
+        }
+    }
+    let summary = counts
+        .iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(kind, count)| format!("{} {}", count, kind.as_str()))
+        .collect::<Vec<_>>();
+    if summary.is_empty() {
+        println!("no functions changed");
+    } else {
+        println!("{}", summary.join(", "));
+    }
+}
+
+fn is_lua_path(path: impl AsRef<Path>) -> bool {
+    path.as_ref().extension().is_some_and(|extension| extension == "lua")
+}
+
+// Parses a file for `hunk-headers`, which leaves alone anything that is not Lua.
+fn parse_lua_file(path: &Path) -> Option<Module> {
+    if is_lua_path(path) {
+        parse_given_file(path)
+    } else {
+        None
+    }
+}
+
+// Parses a file given with `--old` or `--new`, which may be a temporary file with any name.
+fn parse_given_file(path: &Path) -> Option<Module> {
+    Module::parse(&read_source(path).ok()?).ok()
+}
+
+// Path of a file in the diff within the working tree, without the `a/` or `b/`
+// prefix that git adds unless the file exists with it.
+fn working_tree_path(path: &str) -> PathBuf {
+    let path = Path::new(path);
+    if path.exists() {
+        return path.to_owned();
+    }
+    let mut components = path.components();
+    components.next();
+    components.as_path().to_owned()
+}
+
+fn hunk_headers(mut args: &[String]) {
+    let mut old = None;
+    let mut new = None;
+    loop {
+        match args {
+            [option, file, rest @ ..] if option == "--old" => {
+                old = Some(Path::new(file.as_str()));
+                args = rest;
+            }
+            [option, file, rest @ ..] if option == "--new" => {
+                new = Some(Path::new(file.as_str()));
+                args = rest;
+            }
+            _ => break,
+        }
+    }
+    let diff = match args {
+        [] => std::io::read_to_string(std::io::stdin()).unwrap_or_else(|e| failure("<stdin>", e)),
+        [file] => std::fs::read_to_string(file).unwrap_or_else(|e| failure(file, e)),
+        _ => usage_error("`hunk-headers` takes at most one diff"),
+    };
+    // `--old` and `--new` are versions of the one file in the diff.
+    if (old.is_some() || new.is_some()) && file_count(&diff) != 1 {
+        usage_error("`--old` and `--new` can only be used with a diff of one file");
+    }
+    let stdout = std::io::stdout();
+    rewrite_hunk_headers(&diff, stdout.lock(), |old_path, new_path| {
+        if !new_path.or(old_path).is_some_and(is_lua_path) {
+            return FilePair::default();
+        }
+        FilePair {
+            old: old.and_then(parse_given_file),
+            new: match new {
+                Some(new) => parse_given_file(new),
+                None => new_path.and_then(|path| parse_lua_file(&working_tree_path(path))),
+            },
+        }
+    })
+    .unwrap_or_else(|e| failure("<stdout>", e));
+}
+
+fn docs(mut args: &[String]) {
+    let mut html = false;
+    let mut output = "-";
+    loop {
+        match args {
+            [option, rest @ ..] if option == "--html" => {
+                html = true;
+                args = rest;
+            }
+            [option, file, rest @ ..] if option == "-o" => {
+                output = file;
+                args = rest;
+            }
+            _ => break,
+        }
+    }
+    let (parsed, failed) = parse_paths(args);
+    let sources = parsed
+        .iter()
+        .map(|(path, _, module)| DocSource { path, module })
+        .collect::<Vec<_>>();
+    let mut writer: Box<dyn Write> = if output == "-" {
+        Box::new(std::io::stdout())
+    } else {
+        let file = std::fs::File::create(output).unwrap_or_else(|e| failure(output, e));
+        Box::new(std::io::BufWriter::new(file))
+    };
+    if html {
+        write_html(&mut writer, &sources)
+    } else {
+        write_markdown(&mut writer, &sources)
+    }
+    .and_then(|()| writer.flush())
+    .unwrap_or_else(|e| failure(output, e));
+    if failed {
+        exit(EXIT_FAILURE);
+    }
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.as_str(), args),
+        None => usage_error("expected a command"),
+    };
+    let single_arg = |name: &str| match args {
+        [arg] => arg.as_str(),
+        _ => usage_error(format!("`{}` takes exactly one argument", name)),
+    };
+    match command {
+        "list" => list(args),
+        "at" => at(single_arg("at")),
+        "stack" => stack(single_arg("stack")),
+        "annotate" => annotate(single_arg("annotate")),
+        "tags" => tags(args),
+        "docs" => docs(args),
+        "diff" => match args {
+            [old, new] => function_diff(old, new),
+            _ => usage_error("`diff` takes exactly two files"),
+        },
+        "hunk-headers" => hunk_headers(args),
+        "help" | "-h" | "--help" => println!("{}", USAGE),
+        _ => usage_error(format!("unknown command `{}`", command)),
+    }
+}
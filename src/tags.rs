@@ -0,0 +1,113 @@
+use crate::{source_lines, FunctionKind, FunctionNameLine, Module, SourceLine};
+use std::io::{self, Write};
+
+// A parsed Lua file to generate tags for.
+#[derive(Debug, Clone, Copy)]
+pub struct TagSource<'a> {
+    // path as it should appear in the tag file
+    pub path: &'a str,
+    pub code: &'a str,
+    pub module: &'a Module,
+}
+
+impl<'a> TagSource<'a> {
+    // Functions that can be jumped to by name, paired with the line they start on.
+    fn tags(self) -> impl Iterator<Item = (&'a FunctionNameLine, SourceLine<'a>)> {
+        let lines = source_lines(self.code);
+        self.module
+            .function_record()
+            .iter()
+            .filter(|function| function.kind != FunctionKind::Anonymous)
+            .map(move |function| {
+                let line = lines.get(function.start - 1).copied().unwrap_or(SourceLine {
+                    offset: self.code.len(),
+                    text: "",
+                });
+                (function, line)
+            })
+    }
+}
+
+fn kind_letter(kind: FunctionKind) -> char {
+    match kind {
+        FunctionKind::Method => 'm',
+        _ => 'f',
+    }
+}
+
+// Escapes a line for use in a `/^...$/` search pattern.
+fn search_pattern(text: &str) -> String {
+    let mut pattern = String::with_capacity(text.len() + 4);
+    pattern.push_str("/^");
+    for c in text.chars() {
+        if c == '\\' || c == '/' {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push_str("$/");
+    pattern
+}
+
+// Writes a tag file in the extended format of Universal Ctags, sorted by tag name.
+pub fn write_ctags<W: Write>(mut writer: W, sources: &[TagSource]) -> io::Result<()> {
+    let mut tags = Vec::new();
+    for source in sources {
+        let functions = source.module.function_record();
+        for (function, line) in source.tags() {
+            let mut tag = format!(
+                "{}\t{}\t{};\"\t{}\tline:{}",
+                function.name,
+                source.path,
+                search_pattern(line.text),
+                kind_letter(function.kind),
+                function.start,
+            );
+            if let Some(parent) = function.parent {
+                tag.push_str("\tfunction:");
+                tag.push_str(&functions[parent].name);
+            }
+            tags.push(tag);
+        }
+    }
+    tags.sort();
+    writeln!(
+        writer,
+        "!_TAG_FILE_FORMAT\t2\t/extended format; --format=1 will not append ;\" to lines/"
+    )?;
+    writeln!(
+        writer,
+        "!_TAG_FILE_SORTED\t1\t/0=unsorted, 1=sorted, 2=foldcase/"
+    )?;
+    writeln!(
+        writer,
+        "!_TAG_KIND_DESCRIPTION!Lua\tf,function\t/functions/"
+    )?;
+    writeln!(writer, "!_TAG_KIND_DESCRIPTION!Lua\tm,method\t/methods/")?;
+    writeln!(writer, "!_TAG_PROGRAM_NAME\tlua-function-at-line\t//")?;
+    for tag in tags {
+        writeln!(writer, "{}", tag)?;
+    }
+    Ok(())
+}
+
+// Writes a tag file in the format used by Emacs (`TAGS`).
+pub fn write_etags<W: Write>(mut writer: W, sources: &[TagSource]) -> io::Result<()> {
+    for source in sources {
+        let mut section = String::new();
+        for (function, line) in source.tags() {
+            section.push_str(&format!(
+                "{}\x7f{}\x01{},{}\n",
+                line.text, function.name, function.start, line.offset
+            ));
+        }
+        write!(
+            writer,
+            "\x0c\n{},{}\n{}",
+            source.path,
+            section.len(),
+            section
+        )?;
+    }
+    Ok(())
+}
@@ -1,6 +1,6 @@
 #![cfg(test)]
 
-use crate::{gather_function_line_spans, FunctionSpan, FunctionNameSegment};
+use crate::{bytecode::{Chunk, LuaVersion, MAX_DEPTH}, coverage::LuaCovStats, gather_function_line_spans, FunctionKind, FunctionSpan, FunctionNameSegment, Module};
 use full_moon::parse;
 
 #[derive(Debug, Eq, PartialEq)]
@@ -13,8 +13,8 @@ struct Function {
 fn check_result(code: &str, expected: &[Function]) {
     let mut function_spans = Vec::new();
     let code = parse(code).unwrap();
-    gather_function_line_spans(&code.nodes(), &mut function_spans).unwrap();
-    let functions = function_spans.into_iter().map(|FunctionSpan { start, end, name }: FunctionSpan| {
+    gather_function_line_spans(code.nodes(), &mut function_spans).unwrap();
+    let functions = function_spans.into_iter().map(|FunctionSpan { start, end, name, .. }: FunctionSpan| {
         let name = if name.first == FunctionNameSegment::Anonymous && name.middle.is_empty() {
             None
         } else {
@@ -62,7 +62,7 @@ macro_rules! function_spans {
 #[test]
 fn top_level_functions() {
     check_result(
-        &"local function first_do() end
+        "local function first_do() end
         function then_do() end", &function_spans! [
         "first_do"[1-1], "then_do"[2-2],
     ]);
@@ -71,7 +71,7 @@ fn top_level_functions() {
 #[test]
 fn local_function_in_local_function() {
     check_result(
-        &"local function add(y)
+        "local function add(y)
             local function inner()
             end
             return x + y
@@ -83,7 +83,7 @@ fn local_function_in_local_function() {
 #[test]
 fn function_with_fields_in_function_with_fields() {
     check_result(
-        &"function x.y:z()
+        "function x.y:z()
             function a.b.c()
                 local var = const;
             end
@@ -95,7 +95,7 @@ fn function_with_fields_in_function_with_fields() {
 #[test]
 fn spread_out_method_or_function_calls_are_compacted() {
     check_result(
-        &"function
+        "function
         
         very
         .
@@ -292,3 +292,1007 @@ fn function_in_table_literal() {
         "?.get"[22-22], "?.get"[24-24],
     ]);
 }
+
+#[test]
+fn executable_lines_belong_to_innermost_function() {
+    let module = Module::new("local x = 1
+    local function f(a)
+        if a then
+            local g = function()
+                return a
+            end
+        elseif x then
+            x = x +
+                1
+        end
+        repeat
+            a = a - 1
+        until a < 0
+    end
+    return f").unwrap();
+    assert_eq!(module.executable_lines(), &[1, 2, 15]);
+    let executable_lines = module.function_record().iter()
+        .map(|function| (function.name.as_str(), function.executable_lines.as_slice()))
+        .collect::<Vec<_>>();
+    assert_eq!(executable_lines, [("f", &[3, 4, 7, 8, 11, 12, 13][..]), ("g", &[5][..])]);
+}
+
+#[test]
+fn luacov_stats_give_function_coverage() {
+    let stats = LuaCovStats::parse("6:./src/mod.lua
+1 1 0 1 0 0 
+2:other.lua
+0 0 
+").unwrap();
+    assert_eq!(stats.hits("other.lua"), Some(&[0, 0][..]));
+    let hits = stats.hits("/home/user/project/src/mod.lua").unwrap();
+    let module = Module::new("local function called()
+        return 1
+    end
+    local function uncalled()
+        return 2
+    end").unwrap();
+    let coverage = module.coverage(hits).into_iter()
+        .map(|coverage| (coverage.name().to_string(), coverage.hit_lines, coverage.missed_lines))
+        .collect::<Vec<_>>();
+    assert_eq!(coverage, [
+        ("<chunk>".to_string(), vec![1, 4], vec![]),
+        ("called".to_string(), vec![2], vec![]),
+        ("uncalled".to_string(), vec![], vec![5]),
+    ]);
+    assert!(LuaCovStats::parse("3:file.lua\n1 2\n").is_err());
+}
+
+#[test]
+fn unindented_functions_start_on_their_own_line() {
+    check_result("local x = 1
+local function f()
+end
+function g()
+end
+h = function()
+end", &function_spans! [
+        "f"[2-3], "g"[4-5], "h"[6-7],
+    ]);
+}
+
+// Minimal chunks for `local function f() return 1 end` on lines 1-3
+// and `t.g = function() end` on line 4, without instructions or debug info.
+fn lua_51_chunk() -> Vec<u8> {
+    fn int(bytes: &mut Vec<u8>, n: u32) {
+        bytes.extend(&n.to_le_bytes());
+    }
+    fn function(bytes: &mut Vec<u8>, source: Option<&str>, lines: (u32, u32), protos: &[(u32, u32)]) {
+        match source {
+            Some(source) => {
+                bytes.extend(&(source.len() as u64 + 1).to_le_bytes());
+                bytes.extend(source.as_bytes());
+                bytes.push(0);
+            }
+            None => bytes.extend(&0u64.to_le_bytes()),
+        }
+        int(bytes, lines.0);
+        int(bytes, lines.1);
+        bytes.extend(&[0, 0, 2, 2]);
+        // one instruction, one string constant
+        int(bytes, 1);
+        bytes.extend(&[0; 4]);
+        int(bytes, 1);
+        bytes.push(4);
+        bytes.extend(&2u64.to_le_bytes());
+        bytes.extend(b"t\0");
+        int(bytes, protos.len() as u32);
+        for &lines in protos {
+            function(bytes, None, lines, &[]);
+        }
+        // line info, locals, upvalues
+        int(bytes, 0);
+        int(bytes, 0);
+        int(bytes, 0);
+    }
+    let mut bytes = b"\x1bLua\x51\x00\x01\x04\x08\x04\x08\x00".to_vec();
+    function(&mut bytes, Some("@test.lua"), (0, 0), &[(1, 3), (4, 4)]);
+    bytes
+}
+
+fn lua_54_chunk() -> Vec<u8> {
+    fn varint(bytes: &mut Vec<u8>, mut n: usize) {
+        let mut groups = vec![(n & 0x7f) as u8 | 0x80];
+        n >>= 7;
+        while n != 0 {
+            groups.push((n & 0x7f) as u8);
+            n >>= 7;
+        }
+        bytes.extend(groups.iter().rev());
+    }
+    fn function(bytes: &mut Vec<u8>, source: Option<&str>, lines: (usize, usize), protos: &[(usize, usize)]) {
+        match source {
+            Some(source) => {
+                varint(bytes, source.len() + 1);
+                bytes.extend(source.as_bytes());
+            }
+            None => varint(bytes, 0),
+        }
+        varint(bytes, lines.0);
+        varint(bytes, lines.1);
+        bytes.extend(&[0, 1, 2]);
+        // one instruction; integer, float and string constants; one upvalue
+        varint(bytes, 1);
+        bytes.extend(&[0; 4]);
+        varint(bytes, 3);
+        bytes.push(0x03);
+        bytes.extend(&1i64.to_le_bytes());
+        bytes.push(0x13);
+        bytes.extend(&1.5f64.to_le_bytes());
+        bytes.push(0x04);
+        varint(bytes, 200);
+        bytes.extend(&[b'x'; 199]);
+        varint(bytes, 1);
+        bytes.extend(&[1, 0, 0]);
+        varint(bytes, protos.len());
+        for &lines in protos {
+            function(bytes, None, lines, &[]);
+        }
+        // line info, absolute line info, locals, upvalue names
+        varint(bytes, 1);
+        bytes.push(0);
+        varint(bytes, 1);
+        varint(bytes, 0);
+        varint(bytes, 300);
+        varint(bytes, 0);
+        varint(bytes, 0);
+    }
+    let mut bytes = b"\x1bLua\x54\x00\x19\x93\r\n\x1a\n\x04\x08\x08".to_vec();
+    bytes.extend(&0x5678i64.to_le_bytes());
+    bytes.extend(&370.5f64.to_le_bytes());
+    bytes.push(1);
+    function(&mut bytes, Some("@test.lua"), (0, 0), &[(1, 3), (4, 4)]);
+    bytes
+}
+
+#[test]
+fn deeply_nested_binary_chunk_is_an_error() {
+    // Lua 5.1 chunk with functions nested `depth` levels deep in the main function,
+    // each containing the next.
+    fn nested_chunk(depth: usize) -> Vec<u8> {
+        let mut bytes = b"\x1bLua\x51\x00\x01\x04\x08\x04\x08\x00".to_vec();
+        for level in 0..=depth {
+            // no source, lines, counts and sizes, no instructions or constants
+            bytes.extend(&0u64.to_le_bytes());
+            bytes.extend(&(level as u32).to_le_bytes());
+            bytes.extend(&(level as u32).to_le_bytes());
+            bytes.extend(&[0, 0, 2, 2]);
+            bytes.extend(&0u32.to_le_bytes());
+            bytes.extend(&0u32.to_le_bytes());
+            bytes.extend(&u32::from(level < depth).to_le_bytes());
+        }
+        for _ in 0..=depth {
+            // line info, locals, upvalues
+            bytes.extend(&[0; 12]);
+        }
+        bytes
+    }
+    let chunk = Chunk::read(&nested_chunk(MAX_DEPTH)).unwrap();
+    assert_eq!(chunk.main.iter().count(), MAX_DEPTH + 1);
+    let error = Chunk::read(&nested_chunk(MAX_DEPTH + 1)).unwrap_err();
+    assert!(error.contains("nested"), "{}", error);
+}
+
+#[test]
+fn binary_chunk_prototypes_match_functions() {
+    let module = Module::new("local function f()
+        return 1
+    end
+    t.g = function() end").unwrap();
+    for (bytes, version) in [(lua_51_chunk(), LuaVersion::Lua51), (lua_54_chunk(), LuaVersion::Lua54)] {
+        let chunk = Chunk::read(&bytes).unwrap();
+        assert_eq!(chunk.version, version);
+        let matched = module.match_prototypes(&chunk.main).into_iter()
+            .map(|(prototype, function)| (
+                prototype.source.as_deref(),
+                prototype.line_defined,
+                function.map(|function| function.name.as_str()),
+            ))
+            .collect::<Vec<_>>();
+        assert_eq!(matched, [
+            (Some("@test.lua"), 0, None),
+            (Some("@test.lua"), 1, Some("f")),
+            (Some("@test.lua"), 4, Some("t.g")),
+        ]);
+        assert!(Chunk::read(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
+
+#[test]
+fn functions_at_positions() {
+    let module = Module::new("local function outer()
+    local inner = function() end return 1
+end").unwrap();
+    let names = |functions: Vec<&crate::FunctionNameLine>| {
+        functions.into_iter().map(|function| function.name.clone()).collect::<Vec<_>>()
+    };
+    assert_eq!(names(module.get_function_stack(2)), ["outer", "inner"]);
+    assert_eq!(names(module.get_function_stack(3)), ["outer"]);
+    assert!(module.get_function_stack(4).is_empty());
+    assert_eq!(module.get_function_stack_indices(2), [0, 1]);
+    assert_eq!(module.get_function_index_at(2, 33), Some(0));
+    let name_at = |line, column| module.get_function_at(line, column).map(|function| function.name.as_str());
+    assert_eq!(name_at(1, 1), Some("outer"));
+    assert_eq!(name_at(2, 19), Some("inner"));
+    assert_eq!(name_at(2, 32), Some("inner"));
+    assert_eq!(name_at(2, 33), Some("outer"));
+    assert_eq!(name_at(3, 3), Some("outer"));
+    assert_eq!(name_at(3, 4), None);
+}
+
+#[test]
+fn errors_have_positions() {
+    let error = Module::parse("local x = 1\nlocal = 2").unwrap_err();
+    assert_eq!((error.kind, error.position), (crate::ErrorKind::Parse, Some((2, 7))));
+    let error = Module::parse("local x = 'unclosed").unwrap_err();
+    assert_eq!(error.kind, crate::ErrorKind::Tokenize);
+    assert!(error.position.is_some());
+    let error = Module::parse("(f)().x = function() end").unwrap_err();
+    assert_eq!((error.kind, error.position), (crate::ErrorKind::Analysis, Some((1, 1))));
+}
+
+#[test]
+fn multi_line_unexpected_tokens_have_start_positions() {
+    let error = Module::parse("local x = 1\n[[abc\ndef]]").unwrap_err();
+    assert_eq!((error.kind, error.position), (crate::ErrorKind::Parse, Some((2, 1))));
+    let error = Module::parse("local x = 1\n  [[abc\ndef]]").unwrap_err();
+    assert_eq!(error.position, Some((2, 3)));
+    let error = Module::parse("local x = 1 [[abc\ndef]]").unwrap_err();
+    assert_eq!(error.position, Some((1, 13)));
+}
+
+#[test]
+fn function_kinds_segments_and_parents() {
+    let module = Module::new(r#"local function f()
+        local g = function() end
+        h = function() end
+    end
+    function M.x:y() end
+    t["k"] = { function() end }
+    call(function() end)"#).unwrap();
+    let functions = module.function_record().iter()
+        .map(|function| (function.name.as_str(), function.kind, function.segments.clone(), function.parent))
+        .collect::<Vec<_>>();
+    let segments = |segments: &[&str]| segments.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    assert_eq!(functions, [
+        ("f", FunctionKind::Local, segments(&["f"]), None),
+        ("g", FunctionKind::Local, segments(&["g"]), Some(0)),
+        ("h", FunctionKind::Global, segments(&["h"]), Some(0)),
+        ("M.x:y", FunctionKind::Method, segments(&["M", "x", "y"]), None),
+        (r#"t["k"][1]"#, FunctionKind::Field, segments(&["t", r#"["k"]"#, "[1]"]), None),
+        ("?", FunctionKind::Anonymous, segments(&["?"]), None),
+    ]);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_records() {
+    let module = Module::new("local function f()
+        return 1
+    end
+    local t = { g = function() end }").unwrap();
+    let mut ndjson = Vec::new();
+    crate::json::write_lines(&mut ndjson, Some("f.lua"), &module).unwrap();
+    assert_eq!(String::from_utf8(ndjson).unwrap(), concat!(
+        r#"{"file":"f.lua","index":0,"name":"f","segments":["f"],"signature":"f()","kind":"local","start":{"line":1,"column":1},"end":{"line":3,"column":8},"parent":null}"#, "\n",
+        r#"{"file":"f.lua","index":1,"name":"t.g","segments":["t","g"],"signature":"t.g()","kind":"field","start":{"line":4,"column":21},"end":{"line":4,"column":35},"parent":null}"#, "\n",
+    ));
+    let mut document = Vec::new();
+    crate::json::write_document(&mut document, vec![(None, &module)]).unwrap();
+    let document: serde_json::Value = serde_json::from_slice(&document).unwrap();
+    assert_eq!(document[1]["name"], "t.g");
+    assert!(document[1].get("file").is_none());
+}
+
+#[test]
+fn tag_files() {
+    use crate::tags::{write_ctags, write_etags, TagSource};
+    let code = "local M = {}\r\nfunction M.f()\r\n  local function g() end\r\nend\r\nfunction M:m() end\r\ncall(function() end)\r\n";
+    let module = Module::new(code).unwrap();
+    let sources = [TagSource { path: "dir/m.lua", code, module: &module }];
+    let mut ctags = Vec::new();
+    write_ctags(&mut ctags, &sources).unwrap();
+    let ctags = String::from_utf8(ctags).unwrap();
+    assert_eq!(ctags.lines().filter(|line| !line.starts_with("!_")).collect::<Vec<_>>(), [
+        "M.f\tdir/m.lua\t/^function M.f()$/;\"\tf\tline:2",
+        "M:m\tdir/m.lua\t/^function M:m() end$/;\"\tm\tline:5",
+        "g\tdir/m.lua\t/^  local function g() end$/;\"\tf\tline:3\tfunction:M.f",
+    ]);
+    let mut etags = Vec::new();
+    write_etags(&mut etags, &sources).unwrap();
+    let section = "function M.f()\x7fM.f\x012,14\n  local function g() end\x7fg\x013,30\nfunction M:m() end\x7fM:m\x015,61\n";
+    assert_eq!(String::from_utf8(etags).unwrap(), format!("\x0c\ndir/m.lua,{}\n{}", section.len(), section));
+}
+
+#[cfg(feature = "lsp")]
+#[test]
+fn language_server_symbols_folding_and_enclosing_function() {
+    use serde_json::{json, Value};
+
+    fn message(content: Value) -> String {
+        let content = content.to_string();
+        format!("Content-Length: {}\r\n\r\n{}", content.len(), content)
+    }
+
+    let uri = "file:///m.lua";
+    let text = "local function outer()\n  local s = '\u{1F600}' local inner = function() end\nend\n";
+    let input = [
+        message(json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} })),
+        message(json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+            "textDocument": { "uri": uri, "languageId": "lua", "version": 1, "text": "" },
+        } })),
+        message(json!({ "jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+            "textDocument": { "uri": uri, "version": 2 },
+            "contentChanges": [{ "text": text }],
+        } })),
+        message(json!({ "jsonrpc": "2.0", "id": 2, "method": "textDocument/documentSymbol", "params": {
+            "textDocument": { "uri": uri },
+        } })),
+        message(json!({ "jsonrpc": "2.0", "id": 3, "method": "textDocument/foldingRange", "params": {
+            "textDocument": { "uri": uri },
+        } })),
+        message(json!({ "jsonrpc": "2.0", "id": 4, "method": crate::lsp::ENCLOSING_FUNCTION, "params": {
+            "textDocument": { "uri": uri }, "position": { "line": 1, "character": 33 },
+        } })),
+        message(json!({ "jsonrpc": "2.0", "id": 5, "method": "shutdown" })),
+        message(json!({ "jsonrpc": "2.0", "method": "exit" })),
+    ].concat();
+    let mut output = Vec::new();
+    assert!(crate::lsp::run(input.as_bytes(), &mut output).unwrap());
+    let output = String::from_utf8(output).unwrap();
+    let responses = output.split("Content-Length: ").skip(1)
+        .map(|message| serde_json::from_str::<Value>(message.split_once("\r\n\r\n").unwrap().1).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(responses.len(), 5);
+    assert_eq!(responses[0]["result"]["capabilities"]["documentSymbolProvider"], true);
+    let range = |start: (u32, u32), end: (u32, u32)| json!({
+        "start": { "line": start.0, "character": start.1 },
+        "end": { "line": end.0, "character": end.1 },
+    });
+    let inner_range = range((1, 31), (1, 45));
+    assert_eq!(responses[1]["result"], json!([{
+        "name": "outer", "kind": 12, "range": range((0, 0), (2, 3)), "selectionRange": range((0, 15), (0, 20)),
+        "children": [{
+            "name": "inner", "kind": 12, "range": inner_range, "selectionRange": range((1, 23), (1, 28)), "children": [],
+        }],
+    }]));
+    assert_eq!(responses[2]["result"], json!([{ "startLine": 0, "endLine": 1 }]));
+    assert_eq!(responses[3]["result"], json!({ "name": "inner", "range": inner_range, "stack": ["outer", "inner"] }));
+    assert_eq!(responses[4]["result"], Value::Null);
+}
+
+#[test]
+#[cfg(feature = "lsp")]
+fn language_server_rejects_huge_messages() {
+    let input = "Content-Length: 1000000000000\r\n\r\n{}";
+    let error = crate::lsp::run(input.as_bytes(), &mut Vec::new()).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn name_spans() {
+    let module = Module::new("function t.a:b() end
+    t['x'] = function() end
+    local u = { f = function() end, function() end }
+    pcall(function() end)").unwrap();
+    let spans = module.function_record().iter()
+        .map(|function| (function.name.as_str(), function.name_start, function.name_end))
+        .collect::<Vec<_>>();
+    assert_eq!(spans, [
+        ("t.a:b", (1, 10), (1, 15)), ("t['x']", (2, 5), (2, 11)),
+        ("u.f", (3, 17), (3, 18)), ("u[1]", (3, 37), (3, 45)), ("?", (4, 11), (4, 19)),
+    ]);
+}
+
+#[test]
+fn hunk_headers_name_enclosing_functions() {
+    use crate::hunks::{file_count, rewrite_hunk_headers, FilePair};
+
+    let old = Module::new("local function f()
+    return 1
+end
+
+local function g()
+-- comment
+    return 2
+end
+").unwrap();
+    let new = Module::new("local function f()
+    return 1
+end
+
+local function g()
+    return 3
+end
+").unwrap();
+    let diff = "diff --git a/m.lua b/m.lua
+--- a/m.lua
++++ b/m.lua
+@@ -4,5 +4,4 @@ end
+ 
+ local function g()
+--- comment
+-    return 2
++    return 3
+ end
+--- a/README
++++ b/README
+@@ -1 +1 @@ title
+-a
++b
+";
+    let mut output = Vec::new();
+    let mut paths = Vec::new();
+    rewrite_hunk_headers(diff, &mut output, |old_path, new_path| {
+        paths.push((old_path.map(String::from), new_path.map(String::from)));
+        if new_path == Some("b/m.lua") {
+            FilePair { old: Some(old.clone()), new: Some(new.clone()) }
+        } else {
+            FilePair::default()
+        }
+    }).unwrap();
+    assert_eq!(paths, [
+        (Some("a/m.lua".to_string()), Some("b/m.lua".to_string())),
+        (Some("a/README".to_string()), Some("b/README".to_string())),
+    ]);
+    assert_eq!(file_count(diff), paths.len());
+    let expected = diff.replacen("@@ -4,5 +4,4 @@ end", "@@ -4,5 +4,4 @@ g", 1);
+    assert_eq!(String::from_utf8(output).unwrap(), expected);
+}
+
+#[test]
+fn function_diff() {
+    use crate::diff::{diff, ChangeKind};
+
+    let old = Module::new("local function a() return 1 end
+local function b() return 2 end
+local function c() return 3 end
+local function d() return 4 end
+local function e() return 5 end
+local function g() return 8 end
+").unwrap();
+    let new = Module::new("local function b() return 2 end
+local function c() return 3 end
+local function x() return 4 end
+local function a() return 1 end
+local function e() return 6 end
+local function f() return 7 end
+").unwrap();
+    let changes = diff(&old, &new).into_iter()
+        .map(|change| (
+            change.kind,
+            change.old.map(|function| function.name.as_str()),
+            change.new.map(|function| function.name.as_str()),
+        ))
+        .collect::<Vec<_>>();
+    assert_eq!(changes, [
+        (ChangeKind::Unchanged, Some("b"), Some("b")),
+        (ChangeKind::Unchanged, Some("c"), Some("c")),
+        (ChangeKind::Renamed, Some("d"), Some("x")),
+        (ChangeKind::Moved, Some("a"), Some("a")),
+        (ChangeKind::Modified, Some("e"), Some("e")),
+        (ChangeKind::Added, None, Some("f")),
+        (ChangeKind::Removed, Some("g"), None),
+    ]);
+}
+
+#[test]
+fn inserted_anonymous_function_is_added() {
+    use crate::diff::{diff, ChangeKind};
+
+    let old = Module::new("call(function() return 1 end)
+call(function() return 2 end)
+").unwrap();
+    let new = Module::new("call(function() return 0 end)
+call(function() return 1 end)
+call(function() return 2 end)
+").unwrap();
+    let changes = diff(&old, &new).into_iter()
+        .map(|change| (change.kind, change.old.map(|function| function.start), change.new.map(|function| function.start)))
+        .collect::<Vec<_>>();
+    assert_eq!(changes, [
+        (ChangeKind::Added, None, Some(1)),
+        (ChangeKind::Unchanged, Some(1), Some(2)),
+        (ChangeKind::Unchanged, Some(2), Some(3)),
+    ]);
+}
+
+#[test]
+fn fingerprints_ignore_trivia_and_ids_are_stable() {
+    let old = Module::new("local function f(a, b)
+  -- add
+  return a + b
+end
+call(function() end, function() end)
+").unwrap();
+    let new = Module::new("local function g() end
+
+local function f( a,b ) return a+b --[[ add ]] end
+call(function() end, function() end)
+").unwrap();
+    let old = old.function_record();
+    let new = new.function_record();
+    assert_eq!(old[0].fingerprint, new[1].fingerprint);
+    assert_ne!(new[0].fingerprint, new[1].fingerprint);
+    assert_eq!(
+        old.iter().map(|function| function.id).collect::<Vec<_>>(),
+        new[1..].iter().map(|function| function.id).collect::<Vec<_>>(),
+    );
+    // Identical anonymous functions get different ids.
+    assert_eq!(new[2].fingerprint, new[3].fingerprint);
+    assert_ne!(new[2].id, new[3].id);
+}
+
+#[test]
+fn ids_of_nested_functions_do_not_depend_on_the_enclosing_body() {
+    let old = Module::new("local function f()
+  local function g() return 1 end
+  return g
+end
+").unwrap();
+    let new = Module::new("local function f()
+  print('changed')
+  local function g() return 1 end
+  return g() + 1
+end
+").unwrap();
+    let (old, new) = (old.function_record(), new.function_record());
+    assert_ne!(old[0].fingerprint, new[0].fingerprint);
+    assert_ne!(old[0].id, new[0].id);
+    assert_eq!(old[1].fingerprint, new[1].fingerprint);
+    assert_eq!(old[1].id, new[1].id);
+}
+
+#[test]
+fn invalid_utf8_keeps_positions() {
+    use crate::decode_source;
+    let code = b"-- caf\xe9\nlocal s = '\xff\xfe' local function f()\n  return '\xe9t\xe9'\nend\n";
+    let decoded = decode_source(code);
+    assert_eq!(decoded.len(), code.len());
+    assert_eq!(decoded.lines().nth(1), Some("local s = '??' local function f()"));
+    let module = Module::parse_bytes(code).unwrap();
+    let f = &module.function_record()[0];
+    assert_eq!((f.name.as_str(), f.start, f.start_column, f.end), ("f", 2, 16, 4));
+    assert_eq!(decode_source("valid \u{e9}".as_bytes()), "valid \u{e9}");
+}
+
+#[test]
+fn deeply_nested_code() {
+    use crate::{ErrorKind, Options};
+    let depth = 500;
+    let code = format!(
+        "local t = {}function() end{}\nlocal s = 'a'{}\nlocal data = {{{}}}",
+        "{".repeat(depth),
+        "}".repeat(depth),
+        " .. 'a'".repeat(depth),
+        "1 + 1, ".repeat(10 * depth),
+    );
+    assert_eq!(Module::parse(&code).unwrap_err().kind, ErrorKind::Nesting);
+    let options = Options { max_depth: 1000, parser_stack_size: Some(256 << 20), ..Options::default() };
+    let module = Module::parse_with(&code, &options).unwrap();
+    assert_eq!(module.function_record().len(), 1);
+    assert_eq!(module.function_record()[0].segments.len(), depth + 1);
+
+    let error = Module::parse_with(&code, &Options { max_depth: 100, ..Options::default() }).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Nesting);
+    assert_eq!(error.position, Some((1, 111)));
+    let error = Module::parse(&format!("local t = {}{}", "{".repeat(2000), "}".repeat(2000))).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Nesting);
+}
+
+// The stack that `Options::max_depth` documents for each level is enough for the recursion
+// of full_moon, for code that `nesting_depth` counts as that deep.
+#[test]
+fn nesting_depth_is_not_lower_than_the_parser_recursion() {
+    use crate::{nesting_depth, Options};
+    let stack_per_level = if cfg!(debug_assertions) { 256 } else { 40 } << 10;
+    let nested = |prefix: &str, open: &str, inner: &str, close: &str, depth| {
+        format!("{}{}{}{}", prefix, open.repeat(depth), inner, close.repeat(depth))
+    };
+    // full_moon takes exponential time for nested parentheses and indexing.
+    for (code, depth) in [
+        (nested("local t = ", "{", "", "}", 200), 200),
+        (nested("", "function f() ", "", " end", 200), 200),
+        (nested("", "if x then ", "", " end", 200), 200),
+        (nested("", "while x do ", "", " end", 200), 200),
+        (nested("", "repeat ", "", " until x", 200), 200),
+        (nested("local t = ", "f(", "", ")", 200), 200),
+        (nested("local t = ", "- ", "1", "", 200), 200),
+        (nested("local t = 1", "", "", " .. 1", 200), 200),
+        (nested("local t = 1", "", "", " ^ 1", 200), 200),
+        (nested("local t = ", "(", "1", ")", 10), 10),
+        (nested("local t = ", "a[", "1", "]", 10), 10),
+    ] {
+        let tokens = full_moon::tokenizer::tokens(&code).unwrap();
+        let counted = nesting_depth(&tokens, usize::MAX).unwrap();
+        assert!(counted >= depth, "{}: {}", code, counted);
+        let options = Options {
+            max_depth: counted,
+            parser_stack_size: Some((1 << 20) + counted * stack_per_level),
+            ..Options::default()
+        };
+        Module::parse_with(&code, &options).unwrap();
+    }
+}
+
+#[test]
+fn line_numbers_match_the_lua_interpreter() {
+    use crate::source_lines;
+    let lines = ["local function f()", "  local g = function() end", "end", "", "local t = {}", "function t.h() end"];
+    for line_break in ["\n", "\r\n", "\n\r", "\r"] {
+        let code = lines.join(line_break);
+        let module = Module::parse(&code).unwrap();
+        let spans = module.function_record().iter()
+            .map(|function| (function.name.as_str(), function.start, function.start_column, function.end))
+            .collect::<Vec<_>>();
+        assert_eq!(spans, [("f", 1, 1, 3), ("g", 2, 13, 2), ("t.h", 6, 1, 6)], "{:?}", line_break);
+        assert_eq!(module.get_function_stack(2).len(), 2, "{:?}", line_break);
+        let texts = source_lines(&code).iter().map(|line| line.text).collect::<Vec<_>>();
+        assert_eq!(texts, lines, "{:?}", line_break);
+    }
+    // Runs of line breaks are split the same way as by the Lua lexer.
+    assert_eq!(source_lines("a\r\n\r\nb\n\r\nc").len(), 5);
+    assert_eq!(source_lines("a\n\n\r\rb").len(), 4);
+
+    let code = "\u{feff}#!/usr/bin/env lua\r\nlocal function f() end\r\n";
+    let module = Module::parse(code).unwrap();
+    let f = &module.function_record()[0];
+    assert_eq!((f.start, f.start_column), (2, 1));
+    let lines = source_lines(code);
+    assert_eq!((lines[0].offset, lines[0].text), (3, "#!/usr/bin/env lua"));
+    assert_eq!((lines[1].offset, lines[1].text), (23, "local function f() end"));
+    // Only the first line can be skipped.
+    assert!(Module::parse("\n#!/usr/bin/env lua").is_err());
+}
+
+#[test]
+fn line_base_and_inclusive_end_lines() {
+    use crate::{LineBase, Options};
+    let code = "local function f()
+    return 1
+end
+local x = 1
+";
+    let module = Module::parse(code).unwrap();
+    assert_eq!(module.line_base(), LineBase::One);
+    let names = (0..=5).map(|line| module.get_function(line)).collect::<Vec<_>>();
+    assert_eq!(names, [None, Some("f"), Some("f"), Some("f"), None, None]);
+
+    let options = Options { line_base: LineBase::Zero, ..Options::default() };
+    let module = Module::parse_with(code, &options).unwrap();
+    let names = (0..=4).map(|line| module.get_function(line)).collect::<Vec<_>>();
+    assert_eq!(names, [Some("f"), Some("f"), Some("f"), None, None]);
+    assert_eq!(module.get_function_stack(2).len(), 1);
+    assert_eq!(module.get_function_at(0, 1).map(|f| f.name.as_str()), Some("f"));
+    // Records are one-based whatever the base of the lookups.
+    assert_eq!((module.function_record()[0].start, module.function_record()[0].end), (1, 3));
+
+    assert_eq!(LineBase::One.to_one_based(0), None);
+    assert_eq!(LineBase::Zero.to_one_based(0), Some(1));
+    assert_eq!(LineBase::Zero.from_one_based(3), 2);
+}
+
+#[test]
+fn lua_5_2_to_5_4_and_luajit_are_unsupported() {
+    use crate::{Dialect, ErrorKind, Options};
+    for dialect in [Dialect::Lua52, Dialect::Lua53, Dialect::Lua54, Dialect::LuaJit] {
+        let options = Options { dialect, ..Options::default() };
+        let error = Module::parse_with("local x = 1", &options).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Unsupported);
+        assert_eq!(error.message, format!("{} cannot be parsed yet", dialect.as_str()));
+    }
+}
+
+#[cfg(not(feature = "luau"))]
+#[test]
+fn luau_dialect_needs_the_feature() {
+    use crate::{Dialect, ErrorKind, Options};
+    let options = Options { dialect: Dialect::Luau, ..Options::default() };
+    assert_eq!(Module::parse_with("", &options).unwrap_err().kind, ErrorKind::Unsupported);
+}
+
+#[cfg(feature = "luau")]
+#[test]
+fn luau_dialect() {
+    use crate::{Dialect, ErrorKind, Options};
+    let code = r#"type Point = { x: number, y: number }
+local function length(p: Point): number
+    return math.sqrt(p.x * p.x + p.y * p.y)
+end
+local total = 0
+for _, p in ipairs(points) do
+    if p.x == 0 then
+        continue
+    end
+    total += length(p)
+end
+handlers.count += (function(n) return n end)(1)
+"#;
+    let options = Options { dialect: Dialect::Luau, ..Options::default() };
+    let module = Module::parse_with(code, &options).unwrap();
+    let spans = module.function_record().iter()
+        .map(|function| (function.name.as_str(), function.start, function.end))
+        .collect::<Vec<_>>();
+    assert_eq!(spans, [("length", 2, 4), ("?", 12, 12)]);
+    assert_eq!(module.executable_lines(), &[1, 2, 5, 6, 7, 8, 10, 12]);
+
+    let error = Module::parse(code).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Parse);
+    assert_eq!(error.position, Some((1, 1)));
+    assert!(error.message.contains("Luau"), "{}", error.message);
+    let error = Module::parse("local n = 0\nn += 1").unwrap_err();
+    assert_eq!(error.position, Some((2, 3)));
+
+    let ast = full_moon::parse(code).unwrap();
+    assert!(Module::from_ast(&ast).is_err());
+    assert!(Module::from_block_with(ast.nodes(), &options).is_ok());
+}
+
+#[test]
+fn parameters_and_signatures() {
+    let module = Module::parse("local M = {}
+function M.f(a, b, ...) end
+function M:g() end
+call(function(x) end)").unwrap();
+    let signatures = module.function_record().iter().map(|f| f.signature()).collect::<Vec<_>>();
+    assert_eq!(signatures, ["M.f(a, b, ...)", "M:g()", "function(x)"]);
+    let parameters = &module.function_record()[0].parameters;
+    assert_eq!(parameters.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), ["a", "b", "..."]);
+    assert!(parameters.iter().all(|p| p.type_annotation.is_none()));
+}
+
+#[cfg(feature = "luau")]
+#[test]
+fn luau_signatures() {
+    use crate::{Dialect, Options};
+    let options = Options { dialect: Dialect::Luau, ..Options::default() };
+    let module = Module::parse_with("local M = {}
+function M.foo(a: number, b: string?): boolean end
+function M.bar(callback: (number,   string) -> (), options: { verbose: boolean --[[ log ]] }) end
+local function untyped(x, y: number) end", &options).unwrap();
+    let signatures = module.function_record().iter().map(|f| f.signature()).collect::<Vec<_>>();
+    assert_eq!(signatures, [
+        "M.foo(a: number, b: string?): boolean",
+        "M.bar(callback: (number, string) -> (), options: { verbose: boolean })",
+        "untyped(x, y: number)",
+    ]);
+    let foo = &module.function_record()[0];
+    assert_eq!(foo.parameters[1].type_annotation.as_deref(), Some("string?"));
+    assert_eq!(foo.return_type.as_deref(), Some("boolean"));
+}
+
+#[test]
+fn doc_comments() {
+    use crate::doc::{DocParam, DocReturn, ParameterMismatch};
+    let code = r#"local M = {}
+
+-- Not a doc comment.
+function M.undocumented() end
+
+--- Adds two numbers.
+-- Integers stay integers.
+-- @param a the first number
+-- @tparam[opt] number b the second number,
+--   0 by default
+-- @treturn number the sum
+function M.add(a, b) end
+
+---Splits a string.
+---@param s string
+---@param sep? string separator
+---@param max table<string, number> limits
+---@return string[] parts
+M.split = function(s, sep) end
+
+--- Too far from the function.
+
+local function far() end
+
+local t = {
+    --[[-- Field function.
+    @param x a value
+    ]]
+    f = function(x, y) end,
+}
+"#;
+    let module = Module::parse(code).unwrap();
+    let docs = module.function_record().iter()
+        .map(|function| (function.name.as_str(), function.doc.as_ref()))
+        .collect::<Vec<_>>();
+    assert_eq!(docs[0], ("M.undocumented", None));
+    assert_eq!(docs[3], ("far", None));
+
+    let add = docs[1].1.unwrap();
+    assert_eq!(add.description, "Adds two numbers.\nIntegers stay integers.");
+    assert_eq!(add.params, [
+        DocParam { name: "a".into(), type_name: None, optional: false, description: "the first number".into() },
+        DocParam { name: "b".into(), type_name: Some("number".into()), optional: true, description: "the second number,\n  0 by default".into() },
+    ]);
+    assert_eq!(add.returns, [DocReturn { type_name: Some("number".into()), description: "the sum".into() }]);
+
+    let split = docs[2].1.unwrap();
+    assert_eq!(split.description, "Splits a string.");
+    let params = split.params.iter()
+        .map(|param| (param.name.as_str(), param.type_name.as_deref(), param.optional))
+        .collect::<Vec<_>>();
+    assert_eq!(params, [("s", Some("string"), false), ("sep", Some("string"), true), ("max", Some("table<string, number>"), false)]);
+    assert_eq!(split.returns[0].type_name.as_deref(), Some("string[]"));
+    let mismatches = split.check_parameters(&module.function_record()[2].parameters);
+    assert_eq!(mismatches, [ParameterMismatch::NotAParameter("max".into())]);
+
+    let field = docs[4].1.unwrap();
+    assert_eq!(docs[4].0, "t.f");
+    assert_eq!(field.description, "Field function.");
+    assert_eq!(field.params[0].name, "x");
+    let mismatches = field.check_parameters(&module.function_record()[4].parameters);
+    assert_eq!(mismatches, [ParameterMismatch::Undocumented("y".into())]);
+}
+
+#[test]
+fn documentation_of_exported_functions() {
+    use crate::docgen::{exported_functions, write_html, write_markdown, DocSource};
+    let code = "local p = {}
+
+--- Adds two numbers.
+--
+-- Works with <integers> too.
+-- @param a the first number
+-- @tparam[opt] number b the second
+-- @treturn number the sum & <more>
+function p.add(a, b) return a + (b or 0) end
+
+local function helper() end
+
+---@param s string
+function p:trim(s) end
+
+return p
+";
+    let module = Module::parse(code).unwrap();
+    assert_eq!(module.returned_name(), Some("p"));
+    let exported = exported_functions(&module).iter().map(|f| f.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(exported, ["p.add", "p:trim"]);
+    let globals = Module::parse("function f() end local function g() end").unwrap();
+    assert_eq!(globals.returned_name(), None);
+    assert_eq!(exported_functions(&globals)[0].name, "f");
+
+    let sources = [DocSource { path: "lib/my mod.lua", module: &module }];
+    let mut markdown = Vec::new();
+    write_markdown(&mut markdown, &sources).unwrap();
+    assert_eq!(String::from_utf8(markdown).unwrap(), "# `lib/my mod.lua`
+
+## `p.add(a, b)`
+
+Adds two numbers.
+
+Works with &lt;integers&gt; too.
+
+Parameters:
+
+- `a`: the first number
+- `b` (`number`, optional): the second
+
+Returns:
+
+- `number`: the sum &amp; &lt;more&gt;
+
+[Source](lib/my%20mod.lua#L9)
+
+## `p:trim(s)`
+
+Parameters:
+
+- `s` (`string`)
+
+[Source](lib/my%20mod.lua#L14)
+
+");
+    let mut html = Vec::new();
+    write_html(&mut html, &sources).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains("<section id=\"lib/my_mod.lua:p.add\">\n<h2><code>p.add(a, b)</code></h2>\n\
+        <p>Adds two numbers.</p>\n<p>Works with &lt;integers&gt; too.</p>\n"));
+    assert!(html.contains("<li><code>b</code> (<code>number</code>, optional): the second</li>"));
+    assert!(html.contains("<a href=\"lib/my%20mod.lua#L14\">Source</a>"));
+    assert!(html.ends_with("</body>\n</html>\n"));
+}
+
+#[test]
+fn function_visitor() {
+    use crate::{visit_functions, FunctionNode, FunctionVisitor};
+
+    // Records entering and exiting functions, with the number of parameters of each.
+    #[derive(Default)]
+    struct Events(Vec<String>);
+
+    impl<'a> FunctionVisitor<'a> for Events {
+        fn enter_function(&mut self, span: &FunctionSpan<'a>, node: &FunctionNode<'a>) {
+            let parameters = node.body.iter_parameters().count();
+            self.0.push(format!("enter {} {} {}", span.name, node.first_token.token(), parameters));
+        }
+
+        fn exit_function(&mut self, span: &FunctionSpan<'a>, _: &FunctionNode<'a>) {
+            self.0.push(format!("exit {}", span.name));
+        }
+    }
+
+    let ast = parse("local function f(a, b)
+    local g = function() call(function(...) end) end
+end
+function t.h() end").unwrap();
+    let mut events = Events::default();
+    visit_functions(ast.nodes(), &mut events).unwrap();
+    assert_eq!(events.0, [
+        "enter f local 2", "enter g function 0", "enter ? function 1", "exit ?", "exit g", "exit f",
+        "enter t.h function 0", "exit t.h",
+    ]);
+}
+
+#[test]
+fn function_nodes_with_statements() {
+    use crate::function_nodes;
+    use full_moon::ast::Stmt;
+    let ast = parse("local t = { f = function(x) end }
+call(1, function()
+    local function g() end
+end)
+function t.h(a, b, c) end").unwrap();
+    let nodes = function_nodes(ast.nodes()).unwrap();
+    let summary = nodes.iter()
+        .map(|(span, node)| {
+            let statement = match node.statement {
+                Stmt::LocalAssignment(_) => "local assignment",
+                Stmt::FunctionCall(_) => "call",
+                Stmt::LocalFunction(_) => "local function",
+                Stmt::FunctionDeclaration(_) => "function declaration",
+                _ => "other",
+            };
+            (span.name.to_string(), node.body.iter_parameters().count(), statement)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(summary, [
+        ("t.f".to_string(), 1, "local assignment"), ("?".to_string(), 0, "call"),
+        ("g".to_string(), 0, "local function"), ("t.h".to_string(), 3, "function declaration"),
+    ]);
+}
+
+#[test]
+fn module_from_ast() {
+    use full_moon::ast::Stmt;
+    let code = "local M = {}
+function M.f(a)
+    return function() end
+end
+do
+    local function g() end
+    print(g)
+end
+return M
+";
+    let ast = parse(code).unwrap();
+    let module = Module::from_ast(&ast).unwrap();
+    let parsed = Module::parse(code).unwrap();
+    assert_eq!(module.function_record(), parsed.function_record());
+    assert_eq!(module.executable_lines(), parsed.executable_lines());
+    assert_eq!(module.returned_name(), Some("M"));
+    assert_eq!(module.get_function(6), Some("g"));
+
+    let block = ast.nodes().iter_stmts()
+        .find_map(|stmt| match stmt {
+            Stmt::Do(block) => Some(block.block()),
+            _ => None,
+        })
+        .unwrap();
+    let module = Module::from_block(block).unwrap();
+    let names = module.function_record().iter()
+        .map(|function| (function.name.as_str(), function.start))
+        .collect::<Vec<_>>();
+    assert_eq!(names, [("g", 6)]);
+    assert_eq!(module.executable_lines(), &[6, 7]);
+    assert_eq!(module.returned_name(), None);
+}
@@ -1,18 +1,18 @@
 use full_moon::{
     ast::{
-        span::ContainedSpan, Call, Expression, FunctionArgs, FunctionCall,
-        Index, Prefix, Suffix, TableConstructor, UnOp, Value, Var,
-        VarExpression,
+        span::ContainedSpan, Call, Expression, Field, FunctionArgs, FunctionCall,
+        Index, LastStmt, Prefix, Stmt, Suffix, TableConstructor, UnOp, Value,
+        Var, VarExpression,
     },
     tokenizer::TokenReference,
 ast::MethodCall};
 
 pub(crate) trait FirstToken {
-    fn first_token(&self) -> &TokenReference;
+    fn first_token(&self) -> &TokenReference<'_>;
 }
 
 impl<'a> FirstToken for Expression<'a> {
-    fn first_token(&self) -> &TokenReference {
+    fn first_token(&self) -> &TokenReference<'_> {
         match self {
             Expression::Parentheses { contained, .. } => {
                 contained.first_token()
@@ -39,19 +39,19 @@ impl<'a> FirstToken for Expression<'a> {
 }
 
 impl<'a> FirstToken for FunctionCall<'a> {
-    fn first_token(&self) -> &TokenReference {
+    fn first_token(&self) -> &TokenReference<'_> {
         self.prefix().first_token()
     }
 }
 
 impl<'a> FirstToken for TableConstructor<'a> {
-    fn first_token(&self) -> &TokenReference {
+    fn first_token(&self) -> &TokenReference<'_> {
         self.braces().first_token()
     }
 }
 
 impl<'a> FirstToken for Suffix<'a> {
-    fn first_token(&self) -> &TokenReference {
+    fn first_token(&self) -> &TokenReference<'_> {
         match self {
             Suffix::Call(Call::AnonymousCall(args)) => match args {
                 FunctionArgs::Parentheses { parentheses, .. } => {
@@ -69,13 +69,13 @@ impl<'a> FirstToken for Suffix<'a> {
 }
 
 impl<'a> FirstToken for ContainedSpan<'a> {
-    fn first_token(&self) -> &TokenReference {
+    fn first_token(&self) -> &TokenReference<'_> {
         self.tokens().0
     }
 }
 
 impl<'a> FirstToken for Var<'a> {
-    fn first_token(&self) -> &TokenReference {
+    fn first_token(&self) -> &TokenReference<'_> {
         match self {
             Var::Expression(expr) => expr.first_token(),
             Var::Name(name) => name,
@@ -84,7 +84,7 @@ impl<'a> FirstToken for Var<'a> {
 }
 
 impl<'a> FirstToken for Prefix<'a> {
-    fn first_token(&self) -> &TokenReference {
+    fn first_token(&self) -> &TokenReference<'_> {
         match self {
             Prefix::Expression(expr) => expr.first_token(),
             Prefix::Name(name) => name,
@@ -93,13 +93,13 @@ impl<'a> FirstToken for Prefix<'a> {
 }
 
 impl<'a> FirstToken for VarExpression<'a> {
-    fn first_token(&self) -> &TokenReference {
+    fn first_token(&self) -> &TokenReference<'_> {
         self.prefix().first_token()
     }
 }
 
 impl<'a> FirstToken for Index<'a> {
-    fn first_token(&self) -> &TokenReference {
+    fn first_token(&self) -> &TokenReference<'_> {
         match self {
             Index::Brackets { brackets, .. } => brackets.first_token(),
             Index::Dot { dot, .. } => dot,
@@ -108,7 +108,7 @@ impl<'a> FirstToken for Index<'a> {
 }
 
 impl<'a> FirstToken for Call<'a> {
-    fn first_token(&self) -> &TokenReference {
+    fn first_token(&self) -> &TokenReference<'_> {
         match self {
             Call::AnonymousCall(call) => call.first_token(),
             Call::MethodCall(call) => call.first_token(),
@@ -117,13 +117,13 @@ impl<'a> FirstToken for Call<'a> {
 }
 
 impl<'a> FirstToken for MethodCall<'a> {
-    fn first_token(&self) -> &TokenReference {
+    fn first_token(&self) -> &TokenReference<'_> {
         self.name()
     }
 }
 
 impl<'a> FirstToken for FunctionArgs<'a> {
-    fn first_token(&self) -> &TokenReference {
+    fn first_token(&self) -> &TokenReference<'_> {
         match self {
             FunctionArgs::Parentheses { parentheses, .. } => parentheses.first_token(),
             FunctionArgs::String(string) => string,
@@ -131,3 +131,51 @@ impl<'a> FirstToken for FunctionArgs<'a> {
         }
     }
 }
+
+impl<'a> FirstToken for Stmt<'a> {
+    fn first_token(&self) -> &TokenReference<'_> {
+        match self {
+            Stmt::Assignment(asgn) => asgn
+                .var_list()
+                .iter()
+                .next()
+                .expect("an assignment must contain at least one variable")
+                .first_token(),
+            Stmt::Do(do_stmt) => do_stmt.do_token(),
+            Stmt::FunctionCall(call) => call.first_token(),
+            Stmt::FunctionDeclaration(func) => func.function_token(),
+            Stmt::GenericFor(for_stmt) => for_stmt.for_token(),
+            Stmt::If(if_stmt) => if_stmt.if_token(),
+            Stmt::LocalAssignment(asgn) => asgn.local_token(),
+            Stmt::LocalFunction(func) => func.local_token(),
+            Stmt::NumericFor(for_stmt) => for_stmt.for_token(),
+            Stmt::Repeat(repeat_stmt) => repeat_stmt.repeat_token(),
+            Stmt::While(while_stmt) => while_stmt.while_token(),
+            #[cfg(feature = "luau")]
+            Stmt::CompoundAssignment(asgn) => asgn.lhs().first_token(),
+            #[cfg(feature = "luau")]
+            Stmt::Continue(token) => token,
+            #[cfg(feature = "luau")]
+            Stmt::TypeDeclaration(declaration) => declaration.type_token(),
+        }
+    }
+}
+
+impl<'a> FirstToken for Field<'a> {
+    fn first_token(&self) -> &TokenReference<'_> {
+        match self {
+            Field::ExpressionKey { brackets, .. } => brackets.first_token(),
+            Field::NameKey { key, .. } => key,
+            Field::NoKey(value) => value.first_token(),
+        }
+    }
+}
+
+impl<'a> FirstToken for LastStmt<'a> {
+    fn first_token(&self) -> &TokenReference<'_> {
+        match self {
+            LastStmt::Break(token) => token,
+            LastStmt::Return(ret) => ret.token(),
+        }
+    }
+}